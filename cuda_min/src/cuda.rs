@@ -1,11 +1,15 @@
-use crate::Param;
+use crate::{Param, ParamInput, ParamResult, TempAlloc};
 use std::{
+    collections::HashMap,
     ffi::{CStr, CString, c_char, c_int, c_uint, c_void},
-    fmt, iter,
+    fmt,
     marker::PhantomData,
     mem,
     num::NonZero,
+    ops::{BitOr, Deref, DerefMut},
     ptr,
+    sync::Mutex,
+    time::Duration,
 };
 
 // CUDA APIs
@@ -33,6 +37,26 @@ impl fmt::Debug for CUerror {
         }
     }
 }
+/// Error of compiling PTX, carrying the JIT error log (i.e., what `ptxas` reports).
+#[derive(Clone)]
+pub struct CompileError {
+    pub error: CUerror,
+    pub log: String,
+}
+impl fmt::Debug for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.log.is_empty() {
+            write!(f, "{:?}", self.error)
+        } else {
+            write!(f, "{:?}\nJIT error log:\n{}", self.error, self.log)
+        }
+    }
+}
+impl From<CompileError> for CUerror {
+    fn from(value: CompileError) -> Self {
+        value.error
+    }
+}
 #[path = "cuda_error/name_desc.rs"]
 mod dumped;
 #[cfg(feature = "native-error-desc")]
@@ -40,6 +64,18 @@ mod dumped;
 mod dumper;
 
 impl CUerror {
+    /// CUDA_ERROR_INVALID_VALUE, used for arguments rejected before reaching the driver.
+    pub const INVALID_VALUE: CUerror = CUerror(NonZero::new(1).unwrap());
+    /// CUDA_ERROR_NO_DEVICE, no CUDA-capable device is available.
+    pub const NO_DEVICE: CUerror = CUerror(NonZero::new(100).unwrap());
+    /// CUDA_ERROR_INVALID_DEVICE, the ordinal does not correspond to a valid device, or the device does not meet the requirement.
+    pub const INVALID_DEVICE: CUerror = CUerror(NonZero::new(101).unwrap());
+    /// CUDA_ERROR_NOT_READY, returned by queries when the work is not yet finished.
+    pub const NOT_READY: CUerror = CUerror(NonZero::new(600).unwrap());
+    /// The raw error code.
+    pub fn code(&self) -> c_int {
+        self.0.get()
+    }
     const _ASSERT_SIZE_EQUAL: () = assert!(
         mem::size_of::<CUresult>() == mem::size_of::<c_int>(),
         "CUresult must be c_int"
@@ -61,7 +97,7 @@ impl CUerror {
 }
 
 #[repr(transparent)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct CUdevice(c_int);
 
 /// CU context, should be dropped if it is not used anymore. Currently it is Device who creates and drops it.
@@ -69,17 +105,145 @@ pub struct CUdevice(c_int);
 #[repr(transparent)]
 #[derive(Copy, Clone, Debug)]
 pub struct CUcontext(*mut c_void);
+/// Raw module handle, owned and unloaded by `Module`.
 #[repr(transparent)]
 #[derive(Copy, Clone, Debug)]
 pub struct CUmodule<'a>(*mut c_void, PhantomData<&'a ()>);
+/// Raw function handle, wrapped by `Function`.
 #[repr(transparent)]
 #[derive(Copy, Clone, Debug)]
 pub struct CUfunction<'a>(*mut c_void, PhantomData<&'a ()>);
+/// Function handle, which borrows the `Module` it comes from.
+#[derive(Copy, Clone, Debug)]
+pub struct Function<'m> {
+    function: CUfunction<'m>,
+    device: &'m Device,
+}
 #[repr(transparent)]
 #[derive(Copy, Clone, Debug)]
 pub struct CUstream(*mut c_void);
 #[repr(transparent)]
-pub struct PendingResult<'c>(PhantomData<&'c ()>);
+#[derive(Copy, Clone, Debug)]
+pub struct CUevent(*mut c_void);
+#[repr(transparent)]
+#[derive(Copy, Clone, Debug)]
+pub struct CUlinkState(*mut c_void);
+#[repr(transparent)]
+#[derive(Copy, Clone, Debug)]
+pub struct CUmemoryPool(*mut c_void);
+// Driver handles are opaque identifiers, and the driver API is thread-safe as long as the calling thread binds
+// the right context (see `Device::bind`). A link state must not be used concurrently, thus it is `Send` only.
+unsafe impl Send for CUcontext {}
+unsafe impl Sync for CUcontext {}
+unsafe impl Send for CUmodule<'_> {}
+unsafe impl Sync for CUmodule<'_> {}
+unsafe impl Send for CUfunction<'_> {}
+unsafe impl Sync for CUfunction<'_> {}
+unsafe impl Send for CUstream {}
+unsafe impl Sync for CUstream {}
+unsafe impl Send for CUevent {}
+unsafe impl Sync for CUevent {}
+unsafe impl Send for CUmemoryPool {}
+unsafe impl Sync for CUmemoryPool {}
+unsafe impl Send for CUlinkState {}
+/// Properties of a memory pool, see `cuMemPoolCreate`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct CUmemPoolProps {
+    pub alloc_type: c_int,
+    pub handle_types: c_int,
+    pub location_type: c_int,
+    pub location_id: c_int,
+    pub win32_security_attributes: *mut c_void,
+    pub max_size: usize,
+    pub usage: u16,
+    pub reserved: [u8; 54],
+}
+const _ASSERT_POOL_PROPS_SIZE: () = assert!(
+    mem::size_of::<CUmemPoolProps>() == 88,
+    "CUmemPoolProps must match cuda.h"
+);
+/// Raw graph handle, owned by `Graph`.
+#[repr(transparent)]
+#[derive(Copy, Clone, Debug)]
+pub struct CUgraph(*mut c_void);
+/// Raw executable graph handle, owned by `GraphExec`.
+#[repr(transparent)]
+#[derive(Copy, Clone, Debug)]
+pub struct CUgraphExec(*mut c_void);
+/// Raw graph node handle, owned by the graph containing it.
+#[repr(transparent)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CUgraphNode(*mut c_void);
+// Graphs must not be modified concurrently, thus they are `Send` only.
+unsafe impl Send for CUgraph {}
+unsafe impl Send for CUgraphExec {}
+unsafe impl Send for CUgraphNode {}
+unsafe impl Sync for CUgraphNode {}
+/// Parameters of a kernel node (`CUDA_KERNEL_NODE_PARAMS_v2`), see `cuGraphAddKernelNode`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct CUkernelNodeParams {
+    pub func: *mut c_void,
+    pub grid_dim_x: c_uint,
+    pub grid_dim_y: c_uint,
+    pub grid_dim_z: c_uint,
+    pub block_dim_x: c_uint,
+    pub block_dim_y: c_uint,
+    pub block_dim_z: c_uint,
+    pub shared_mem_bytes: c_uint,
+    pub kernel_params: *mut *mut c_void,
+    pub extra: *mut *mut c_void,
+    pub kern: *mut c_void,
+    pub ctx: CUcontext,
+}
+const _ASSERT_KERNEL_NODE_PARAMS_SIZE: () = assert!(
+    mem::size_of::<CUkernelNodeParams>() == 72,
+    "CUkernelNodeParams must match cuda.h"
+);
+/// Parameters of a copy (`CUDA_MEMCPY3D`), only 1d copies between host and device memory are used in this crate.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct CUmemcpy3D {
+    pub src_x_in_bytes: usize,
+    pub src_y: usize,
+    pub src_z: usize,
+    pub src_lod: usize,
+    pub src_memory_type: c_int,
+    pub src_host: *const c_void,
+    pub src_device: *const c_void,
+    pub src_array: *mut c_void,
+    pub reserved0: *mut c_void,
+    pub src_pitch: usize,
+    pub src_height: usize,
+    pub dst_x_in_bytes: usize,
+    pub dst_y: usize,
+    pub dst_z: usize,
+    pub dst_lod: usize,
+    pub dst_memory_type: c_int,
+    pub dst_host: *mut c_void,
+    pub dst_device: *mut c_void,
+    pub dst_array: *mut c_void,
+    pub reserved1: *mut c_void,
+    pub dst_pitch: usize,
+    pub dst_height: usize,
+    pub width_in_bytes: usize,
+    pub height: usize,
+    pub depth: usize,
+}
+const _ASSERT_MEMCPY3D_SIZE: () = assert!(
+    mem::size_of::<CUmemcpy3D>() == 200,
+    "CUmemcpy3D must match cuda.h"
+);
+/// A launched kernel whose result is not yet copied back, bound to the stream it was launched on.
+/// Temporary device buffers of the launch are released once it is synced (or dropped).
+pub struct PendingResult<'c> {
+    device: &'c Device,
+    stream: CUstream,
+    temporaries: Vec<DeviceBuffer<'c, u8>>,
+    /// whether dropping it should wait for the launch, since host memory or temporaries are still in use.
+    pending: bool,
+}
 // 手动绑定 CUDA 驱动 API
 #[link(name = "cuda")]
 unsafe extern "C" {
@@ -91,6 +255,48 @@ unsafe extern "C" {
     pub fn cuDeviceGetCount(count: &mut c_int) -> CUresult;
     #[must_use = "You should check whether the execution successes."]
     pub fn cuDeviceGet(device: *mut CUdevice, ordinal: c_int) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuDevicePrimaryCtxRetain(ctx: *mut CUcontext, dev: CUdevice) -> CUresult;
+    #[cfg_attr(
+        feature = "using_v2_suffix",
+        link_name = "cuDevicePrimaryCtxRelease_v2"
+    )]
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuDevicePrimaryCtxRelease(dev: CUdevice) -> CUresult;
+    #[cfg_attr(
+        feature = "using_v2_suffix",
+        link_name = "cuDevicePrimaryCtxSetFlags_v2"
+    )]
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuDevicePrimaryCtxSetFlags(dev: CUdevice, flags: c_uint) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuDevicePrimaryCtxGetState(
+        dev: CUdevice,
+        flags: &mut c_uint,
+        active: &mut c_int,
+    ) -> CUresult;
+    /// Flags of the current context.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuCtxGetFlags(flags: &mut c_uint) -> CUresult;
+    #[cfg_attr(feature = "using_v2_suffix", link_name = "cuCtxPushCurrent_v2")]
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuCtxPushCurrent(ctx: CUcontext) -> CUresult;
+    #[cfg_attr(feature = "using_v2_suffix", link_name = "cuCtxPopCurrent_v2")]
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuCtxPopCurrent(ctx: *mut CUcontext) -> CUresult;
+    /// Free and total memory of the device of the current context, in bytes.
+    #[cfg_attr(feature = "using_v2_suffix", link_name = "cuMemGetInfo_v2")]
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuMemGetInfo(free: &mut usize, total: &mut usize) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuDeviceGetName(name: *mut c_char, len: c_int, dev: CUdevice) -> CUresult;
+    #[cfg_attr(feature = "using_v2_suffix", link_name = "cuDeviceTotalMem_v2")]
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuDeviceTotalMem(bytes: &mut usize, dev: CUdevice) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuDeviceGetUuid(uuid: *mut [u8; 16], dev: CUdevice) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuDeviceGetPCIBusId(pci_bus_id: *mut c_char, len: c_int, dev: CUdevice) -> CUresult;
     #[cfg_attr(feature = "using_v2_suffix", link_name = "cuCtxCreate_v2")]
     #[must_use = "You should check whether the execution successes."]
     pub fn cuCtxCreate(ctx: *mut CUcontext, flags: c_uint, dev: CUdevice) -> CUresult;
@@ -106,6 +312,55 @@ unsafe extern "C" {
     #[must_use = "You should check whether the execution successes."]
     pub fn cuModuleLoadData(module: *mut CUmodule, ptx: *const c_char) -> CUresult;
     #[must_use = "You should check whether the execution successes."]
+    pub fn cuModuleLoadFatBinary(module: *mut CUmodule, fat_cubin: *const c_void) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuModuleLoadDataEx(
+        module: *mut CUmodule,
+        image: *const c_void,
+        num_options: c_uint,
+        options: *mut c_int,
+        option_values: *mut *mut c_void,
+    ) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuModuleUnload(module: CUmodule) -> CUresult;
+    #[cfg_attr(feature = "using_v2_suffix", link_name = "cuModuleGetGlobal_v2")]
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuModuleGetGlobal(
+        dptr: *mut *mut c_void,
+        bytes: *mut usize,
+        module: CUmodule,
+        name: *const c_char,
+    ) -> CUresult;
+    #[cfg_attr(feature = "using_v2_suffix", link_name = "cuLinkCreate_v2")]
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuLinkCreate(
+        num_options: c_uint,
+        options: *mut c_int,
+        option_values: *mut *mut c_void,
+        state: *mut CUlinkState,
+    ) -> CUresult;
+    #[cfg_attr(feature = "using_v2_suffix", link_name = "cuLinkAddData_v2")]
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuLinkAddData(
+        state: CUlinkState,
+        input_type: c_int,
+        data: *mut c_void,
+        size: usize,
+        name: *const c_char,
+        num_options: c_uint,
+        options: *mut c_int,
+        option_values: *mut *mut c_void,
+    ) -> CUresult;
+    /// The output image is owned by the link state, and is freed by `cuLinkDestroy`
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuLinkComplete(
+        state: CUlinkState,
+        cubin_out: *mut *mut c_void,
+        size_out: *mut usize,
+    ) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuLinkDestroy(state: CUlinkState) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
     pub fn cuModuleGetFunction(
         func: *mut CUfunction,
         module: CUmodule,
@@ -114,6 +369,97 @@ unsafe extern "C" {
     #[cfg_attr(feature = "using_v2_suffix", link_name = "cuMemAlloc_v2")]
     #[must_use = "You should check whether the execution successes."]
     pub fn cuMemAlloc(dptr: *mut *mut c_void, bytesize: usize) -> CUresult;
+    #[cfg_attr(feature = "using_v2_suffix", link_name = "cuMemFree_v2")]
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuMemFree(dptr: *mut c_void) -> CUresult;
+    /// Allocate unified memory, flags must be 1 (CU_MEM_ATTACH_GLOBAL) or 2 (CU_MEM_ATTACH_HOST)
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuMemAllocManaged(dptr: *mut *mut c_void, bytesize: usize, flags: c_uint) -> CUresult;
+    /// Prefetch unified memory, `dst_device` could be -1 (CU_DEVICE_CPU) for host
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuMemPrefetchAsync(
+        dptr: *const c_void,
+        count: usize,
+        dst_device: CUdevice,
+        stream: CUstream,
+    ) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuMemAdvise(
+        dptr: *const c_void,
+        count: usize,
+        advice: c_int,
+        device: CUdevice,
+    ) -> CUresult;
+    #[cfg_attr(feature = "using_v2_suffix", link_name = "cuMemAllocHost_v2")]
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuMemAllocHost(pp: *mut *mut c_void, bytesize: usize) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuMemFreeHost(p: *mut c_void) -> CUresult;
+    #[cfg_attr(feature = "using_v2_suffix", link_name = "cuMemHostRegister_v2")]
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuMemHostRegister(p: *mut c_void, bytesize: usize, flags: c_uint) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuMemHostUnregister(p: *mut c_void) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuMemAllocAsync(dptr: *mut *mut c_void, bytesize: usize, stream: CUstream) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuMemAllocFromPoolAsync(
+        dptr: *mut *mut c_void,
+        bytesize: usize,
+        pool: CUmemoryPool,
+        stream: CUstream,
+    ) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuMemFreeAsync(dptr: *mut c_void, stream: CUstream) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuDeviceGetDefaultMemPool(pool: *mut CUmemoryPool, dev: CUdevice) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuMemPoolCreate(pool: *mut CUmemoryPool, props: *const CUmemPoolProps) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuMemPoolDestroy(pool: CUmemoryPool) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuMemPoolSetAttribute(pool: CUmemoryPool, attr: c_int, value: *mut c_void) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuMemPoolGetAttribute(pool: CUmemoryPool, attr: c_int, value: *mut c_void) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuMemPoolTrimTo(pool: CUmemoryPool, min_bytes_to_keep: usize) -> CUresult;
+    #[cfg_attr(feature = "using_v2_suffix", link_name = "cuMemsetD8_v2")]
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuMemsetD8(dst: *mut c_void, value: u8, count: usize) -> CUresult;
+    #[cfg_attr(feature = "using_v2_suffix", link_name = "cuMemcpyHtoD_v2")]
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuMemcpyHtoD(dst: *mut c_void, src: *const c_void, bytesize: usize) -> CUresult;
+    #[cfg_attr(feature = "using_v2_suffix", link_name = "cuMemcpyDtoH_v2")]
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuMemcpyDtoH(dst: *mut c_void, src: *const c_void, bytesize: usize) -> CUresult;
+    #[cfg_attr(feature = "using_v2_suffix", link_name = "cuMemcpyDtoD_v2")]
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuMemcpyDtoD(dst: *mut c_void, src: *const c_void, bytesize: usize) -> CUresult;
+    #[cfg_attr(feature = "using_v2_suffix", link_name = "cuMemcpyDtoDAsync_v2")]
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuMemcpyDtoDAsync(
+        dst: *mut c_void,
+        src: *const c_void,
+        bytesize: usize,
+        stream: CUstream,
+    ) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuMemcpyPeerAsync(
+        dst: *mut c_void,
+        dst_context: CUcontext,
+        src: *const c_void,
+        src_context: CUcontext,
+        bytesize: usize,
+        stream: CUstream,
+    ) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuDeviceCanAccessPeer(can_access: &mut c_int, dev: CUdevice, peer: CUdevice)
+    -> CUresult;
+    /// Allow the current context to access the memory of `peer_context`, flags must be 0.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuCtxEnablePeerAccess(peer_context: CUcontext, flags: c_uint) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuCtxDisablePeerAccess(peer_context: CUcontext) -> CUresult;
     #[cfg_attr(feature = "using_v2_suffix", link_name = "cuMemcpyHtoDAsync_v2")]
     #[must_use = "You should check whether the execution successes."]
     pub fn cuMemcpyHtoDAsync(
@@ -148,52 +494,726 @@ unsafe extern "C" {
     #[must_use = "You should check whether the execution successes."]
     pub fn cuFuncGetAttribute(result: &mut c_int, attrib: c_int, func: CUfunction) -> CUresult;
     #[must_use = "You should check whether the execution successes."]
+    pub fn cuFuncSetAttribute(func: CUfunction, attrib: c_int, value: c_int) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuFuncSetCacheConfig(func: CUfunction, config: c_int) -> CUresult;
+    /// `block_size_to_dynamic_smem` could be null, then `dynamic_smem_size` is used for every block size.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuOccupancyMaxPotentialBlockSize(
+        min_grid_size: &mut c_int,
+        block_size: &mut c_int,
+        func: CUfunction,
+        block_size_to_dynamic_smem: Option<extern "C" fn(c_int) -> usize>,
+        dynamic_smem_size: usize,
+        block_size_limit: c_int,
+    ) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuOccupancyMaxActiveBlocksPerMultiprocessor(
+        num_blocks: &mut c_int,
+        func: CUfunction,
+        block_size: c_int,
+        dynamic_smem_size: usize,
+    ) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
     pub fn cuCtxSynchronize() -> CUresult; // not used yet.
+    /// `mode` is a `CUstreamCaptureMode`, only the `_v2` version accepts it.
+    #[link_name = "cuStreamBeginCapture_v2"]
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuStreamBeginCapture(stream: CUstream, mode: c_int) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuStreamEndCapture(stream: CUstream, graph: *mut CUgraph) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuGraphCreate(graph: *mut CUgraph, flags: c_uint) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuGraphDestroy(graph: CUgraph) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuGraphGetNodes(
+        graph: CUgraph,
+        nodes: *mut CUgraphNode,
+        num_nodes: &mut usize,
+    ) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuGraphNodeGetType(node: CUgraphNode, node_type: &mut c_int) -> CUresult;
+    #[cfg_attr(feature = "using_v2_suffix", link_name = "cuGraphAddKernelNode_v2")]
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuGraphAddKernelNode(
+        node: *mut CUgraphNode,
+        graph: CUgraph,
+        dependencies: *const CUgraphNode,
+        num_dependencies: usize,
+        params: *const CUkernelNodeParams,
+    ) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuGraphAddMemcpyNode(
+        node: *mut CUgraphNode,
+        graph: CUgraph,
+        dependencies: *const CUgraphNode,
+        num_dependencies: usize,
+        copy_params: *const CUmemcpy3D,
+        ctx: CUcontext,
+    ) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuGraphInstantiateWithFlags(
+        exec: *mut CUgraphExec,
+        graph: CUgraph,
+        flags: u64,
+    ) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuGraphExecDestroy(exec: CUgraphExec) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuGraphLaunch(exec: CUgraphExec, stream: CUstream) -> CUresult;
+    #[cfg_attr(
+        feature = "using_v2_suffix",
+        link_name = "cuGraphExecKernelNodeSetParams_v2"
+    )]
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuGraphExecKernelNodeSetParams(
+        exec: CUgraphExec,
+        node: CUgraphNode,
+        params: *const CUkernelNodeParams,
+    ) -> CUresult;
     #[must_use = "You should check whether the execution successes."]
     pub fn cuStreamSynchronize(stream: CUstream) -> CUresult;
-    /// Create stream, flags must be 0
+    /// Create stream, flags could be 0 (CU_STREAM_DEFAULT) or 1 (CU_STREAM_NON_BLOCKING)
     #[must_use = "You should check whether the execution successes."]
     pub fn cuStreamCreate(stream: *mut CUstream, flags: u32) -> CUresult;
+    /// Create stream with priority, lower numbers represent higher priorities
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuStreamCreateWithPriority(
+        stream: *mut CUstream,
+        flags: u32,
+        priority: c_int,
+    ) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuStreamGetPriority(stream: CUstream, priority: &mut c_int) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuStreamGetFlags(stream: CUstream, flags: &mut c_uint) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuCtxGetStreamPriorityRange(
+        least_priority: &mut c_int,
+        greatest_priority: &mut c_int,
+    ) -> CUresult;
     /// Destroy stream
+    #[cfg_attr(feature = "using_v2_suffix", link_name = "cuStreamDestroy_v2")]
     #[must_use = "You should check whether the execution successes."]
     pub fn cuStreamDestroy(stream: CUstream) -> CUresult;
     /// Query stream
     #[must_use = "You should check whether the execution successes."]
     pub fn cuStreamQuery(stream: CUstream) -> CUresult;
+    /// Make stream wait for an event, flags must be 0
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuStreamWaitEvent(stream: CUstream, event: CUevent, flags: c_uint) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuEventCreate(event: *mut CUevent, flags: c_uint) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuEventRecord(event: CUevent, stream: CUstream) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuEventQuery(event: CUevent) -> CUresult;
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuEventSynchronize(event: CUevent) -> CUresult;
+    /// Elapsed time between two events, in milliseconds
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuEventElapsedTime(milliseconds: &mut f32, start: CUevent, end: CUevent) -> CUresult;
+    #[cfg_attr(feature = "using_v2_suffix", link_name = "cuEventDestroy_v2")]
+    #[must_use = "You should check whether the execution successes."]
+    pub fn cuEventDestroy(event: CUevent) -> CUresult;
 }
 
+/// Device attributes, according to <https://docs.nvidia.com/cuda/cuda-driver-api/group__CUDA__TYPES.html> (`CUdevice_attribute`)
+///
+/// Sizes are in bytes unless stated otherwise, boolean attributes are 0 or 1.
+#[repr(i32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DeviceAttribute {
+    MaxThreadsPerBlock = 1,
+    MaxBlockDimX = 2,
+    MaxBlockDimY = 3,
+    MaxBlockDimZ = 4,
+    MaxGridDimX = 5,
+    MaxGridDimY = 6,
+    MaxGridDimZ = 7,
+    MaxSharedMemoryPerBlock = 8,
+    TotalConstantMemory = 9,
+    WarpSize = 10,
+    MaxPitch = 11,
+    MaxRegistersPerBlock = 12,
+    /// Peak clock frequency in kilohertz.
+    ClockRate = 13,
+    TextureAlignment = 14,
+    GpuOverlap = 15,
+    MultiprocessorCount = 16,
+    KernelExecTimeout = 17,
+    Integrated = 18,
+    CanMapHostMemory = 19,
+    /// Compute mode, 0 default, 2 prohibited, 3 exclusive process.
+    ComputeMode = 20,
+    MaximumTexture1dWidth = 21,
+    MaximumTexture2dWidth = 22,
+    MaximumTexture2dHeight = 23,
+    MaximumTexture3dWidth = 24,
+    MaximumTexture3dHeight = 25,
+    MaximumTexture3dDepth = 26,
+    MaximumTexture2dLayeredWidth = 27,
+    MaximumTexture2dLayeredHeight = 28,
+    MaximumTexture2dLayeredLayers = 29,
+    SurfaceAlignment = 30,
+    ConcurrentKernels = 31,
+    EccEnabled = 32,
+    PciBusId = 33,
+    PciDeviceId = 34,
+    TccDriver = 35,
+    /// Peak memory clock frequency in kilohertz.
+    MemoryClockRate = 36,
+    /// Global memory bus width in bits.
+    GlobalMemoryBusWidth = 37,
+    L2CacheSize = 38,
+    MaxThreadsPerMultiprocessor = 39,
+    AsyncEngineCount = 40,
+    UnifiedAddressing = 41,
+    MaximumTexture1dLayeredWidth = 42,
+    MaximumTexture1dLayeredLayers = 43,
+    /// Deprecated, use `MaximumTexture2dGatherWidth`.
+    CanTex2dGather = 44,
+    MaximumTexture2dGatherWidth = 45,
+    MaximumTexture2dGatherHeight = 46,
+    MaximumTexture3dWidthAlternate = 47,
+    MaximumTexture3dHeightAlternate = 48,
+    MaximumTexture3dDepthAlternate = 49,
+    PciDomainId = 50,
+    TexturePitchAlignment = 51,
+    MaximumTexturecubemapWidth = 52,
+    MaximumTexturecubemapLayeredWidth = 53,
+    MaximumTexturecubemapLayeredLayers = 54,
+    MaximumSurface1dWidth = 55,
+    MaximumSurface2dWidth = 56,
+    MaximumSurface2dHeight = 57,
+    MaximumSurface3dWidth = 58,
+    MaximumSurface3dHeight = 59,
+    MaximumSurface3dDepth = 60,
+    MaximumSurface1dLayeredWidth = 61,
+    MaximumSurface1dLayeredLayers = 62,
+    MaximumSurface2dLayeredWidth = 63,
+    MaximumSurface2dLayeredHeight = 64,
+    MaximumSurface2dLayeredLayers = 65,
+    MaximumSurfacecubemapWidth = 66,
+    MaximumSurfacecubemapLayeredWidth = 67,
+    MaximumSurfacecubemapLayeredLayers = 68,
+    /// Deprecated, do not use.
+    MaximumTexture1dLinearWidth = 69,
+    MaximumTexture2dLinearWidth = 70,
+    MaximumTexture2dLinearHeight = 71,
+    MaximumTexture2dLinearPitch = 72,
+    MaximumTexture2dMipmappedWidth = 73,
+    MaximumTexture2dMipmappedHeight = 74,
+    ComputeCapabilityMajor = 75,
+    ComputeCapabilityMinor = 76,
+    MaximumTexture1dMipmappedWidth = 77,
+    StreamPrioritiesSupported = 78,
+    GlobalL1CacheSupported = 79,
+    LocalL1CacheSupported = 80,
+    MaxSharedMemoryPerMultiprocessor = 81,
+    MaxRegistersPerMultiprocessor = 82,
+    ManagedMemory = 83,
+    MultiGpuBoard = 84,
+    MultiGpuBoardGroupId = 85,
+    HostNativeAtomicSupported = 86,
+    SingleToDoublePrecisionPerfRatio = 87,
+    PageableMemoryAccess = 88,
+    ConcurrentManagedAccess = 89,
+    ComputePreemptionSupported = 90,
+    CanUseHostPointerForRegisteredMem = 91,
+    /// Deprecated along with the v1 stream memory operations.
+    CanUseStreamMemOpsV1 = 92,
+    CanUse64BitStreamMemOpsV1 = 93,
+    CanUseStreamWaitValueNorV1 = 94,
+    CooperativeLaunch = 95,
+    CooperativeMultiDeviceLaunch = 96,
+    /// Maximum shared memory per block usable by opting in with `Function::set_max_dynamic_shared_mem`.
+    MaxSharedMemoryPerBlockOptin = 97,
+    CanFlushRemoteWrites = 98,
+    HostRegisterSupported = 99,
+    PageableMemoryAccessUsesHostPageTables = 100,
+    DirectManagedMemAccessFromHost = 101,
+    VirtualMemoryManagementSupported = 102,
+    HandleTypePosixFileDescriptorSupported = 103,
+    HandleTypeWin32HandleSupported = 104,
+    HandleTypeWin32KmtHandleSupported = 105,
+    MaxBlocksPerMultiprocessor = 106,
+    GenericCompressionSupported = 107,
+    MaxPersistingL2CacheSize = 108,
+    MaxAccessPolicyWindowSize = 109,
+    GpuDirectRdmaWithCudaVmmSupported = 110,
+    ReservedSharedMemoryPerBlock = 111,
+    SparseCudaArraySupported = 112,
+    ReadOnlyHostRegisterSupported = 113,
+    TimelineSemaphoreInteropSupported = 114,
+    MemoryPoolsSupported = 115,
+    GpuDirectRdmaSupported = 116,
+    GpuDirectRdmaFlushWritesOptions = 117,
+    GpuDirectRdmaWritesOrdering = 118,
+    MempoolSupportedHandleTypes = 119,
+    ClusterLaunch = 120,
+    DeferredMappingCudaArraySupported = 121,
+    CanUse64BitStreamMemOps = 122,
+    CanUseStreamWaitValueNor = 123,
+    DmaBufSupported = 124,
+    IpcEventSupported = 125,
+    MemSyncDomainCount = 126,
+    TensorMapAccessSupported = 127,
+    HandleTypeFabricSupported = 128,
+    UnifiedFunctionPointers = 129,
+    NumaConfig = 130,
+    NumaId = 131,
+    MulticastSupported = 132,
+    MpsEnabled = 133,
+    HostNumaId = 134,
+}
+/// A snapshot of the most useful properties of a device, see `Device::properties`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceProperties {
+    pub name: String,
+    pub uuid: [u8; 16],
+    pub pci_bus_id: String,
+    /// (major, minor)
+    pub compute_capability: (i32, i32),
+    /// in bytes
+    pub total_memory: usize,
+    pub multiprocessor_count: i32,
+    pub warp_size: i32,
+    pub max_threads_per_block: i32,
+    pub max_threads_per_multiprocessor: i32,
+    pub max_block_dim: [i32; 3],
+    pub max_grid_dim: [i32; 3],
+    /// in bytes
+    pub max_shared_memory_per_block: i32,
+    /// in bytes, the limit after opting in with `Function::set_max_dynamic_shared_mem`
+    pub max_shared_memory_per_block_optin: i32,
+    /// in bytes
+    pub max_shared_memory_per_multiprocessor: i32,
+    pub max_registers_per_block: i32,
+    /// in bytes
+    pub total_constant_memory: i32,
+    /// in bytes
+    pub l2_cache_size: i32,
+    /// in kHz
+    pub clock_rate: i32,
+    /// in kHz
+    pub memory_clock_rate: i32,
+    /// in bits
+    pub global_memory_bus_width: i32,
+    pub async_engine_count: i32,
+    pub integrated: bool,
+    pub ecc_enabled: bool,
+    pub unified_addressing: bool,
+    pub managed_memory: bool,
+    pub concurrent_managed_access: bool,
+}
+impl fmt::Display for DeviceProperties {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (major, minor) = self.compute_capability;
+        writeln!(f, "{} (sm_{major}{minor})", self.name)?;
+        write!(f, "  UUID: GPU")?;
+        for (i, byte) in self.uuid.iter().enumerate() {
+            if matches!(i, 0 | 4 | 6 | 8 | 10) {
+                write!(f, "-")?;
+            }
+            write!(f, "{byte:02x}")?;
+        }
+        writeln!(f)?;
+        writeln!(f, "  PCI bus id: {}", self.pci_bus_id)?;
+        writeln!(
+            f,
+            "  Memory: {} MiB, {}-bit bus at {} MHz, L2 cache {} KiB",
+            self.total_memory >> 20,
+            self.global_memory_bus_width,
+            self.memory_clock_rate / 1000,
+            self.l2_cache_size >> 10
+        )?;
+        writeln!(
+            f,
+            "  Multiprocessors: {} at {} MHz, warp size {}",
+            self.multiprocessor_count,
+            self.clock_rate / 1000,
+            self.warp_size
+        )?;
+        writeln!(
+            f,
+            "  Threads: {} per block, {} per multiprocessor, block dims {:?}, grid dims {:?}",
+            self.max_threads_per_block,
+            self.max_threads_per_multiprocessor,
+            self.max_block_dim,
+            self.max_grid_dim
+        )?;
+        writeln!(
+            f,
+            "  Shared memory: {} bytes per block ({} opt-in), {} bytes per multiprocessor",
+            self.max_shared_memory_per_block,
+            self.max_shared_memory_per_block_optin,
+            self.max_shared_memory_per_multiprocessor
+        )?;
+        writeln!(
+            f,
+            "  Registers: {} per block, constant memory: {} bytes, async engines: {}",
+            self.max_registers_per_block, self.total_constant_memory, self.async_engine_count
+        )?;
+        write!(
+            f,
+            "  Integrated: {}, ECC: {}, unified addressing: {}, managed memory: {}, concurrent managed access: {}",
+            self.integrated,
+            self.ecc_enabled,
+            self.unified_addressing,
+            self.managed_memory,
+            self.concurrent_managed_access
+        )
+    }
+}
+/// Environment variable pinning the GPU used by `Device::init`, `Device::with_most_free_memory` and `DeviceInfo::select_min_sm`,
+/// e.g., `CUDA_MIN_DEVICE=1`.
+pub const DEVICE_ENV: &str = "CUDA_MIN_DEVICE";
+/// Lightweight description of a GPU, queried without creating any context.
+///
+/// Use `DeviceInfo::list` to enumerate the GPUs, `select_min_sm` to pick one, and `open` to create a `Device` on it.
+/// Free memory needs a context, thus it is queried with `Device::free_memory` or `Device::with_most_free_memory` instead.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub ordinal: i32,
+    pub name: String,
+    /// in bytes
+    pub total_memory: usize,
+    /// (major, minor)
+    pub compute_capability: (i32, i32),
+    device: CUdevice,
+}
+impl fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "#{}: {} (sm_{}), {} MiB",
+            self.ordinal,
+            self.name,
+            self.sm(),
+            self.total_memory >> 20
+        )
+    }
+}
+impl DeviceInfo {
+    /// Query the device of `ordinal`.
+    pub fn get(ordinal: i32) -> Result<Self, CUerror> {
+        let mut device = CUdevice(0);
+        let mut major = 0;
+        let mut minor = 0;
+        unsafe {
+            cuInit(0)?;
+            cuDeviceGet(&mut device, ordinal)?;
+            cuDeviceGetAttribute(
+                &mut major,
+                DeviceAttribute::ComputeCapabilityMajor as c_int,
+                device,
+            )?;
+            cuDeviceGetAttribute(
+                &mut minor,
+                DeviceAttribute::ComputeCapabilityMinor as c_int,
+                device,
+            )?;
+        }
+        Ok(Self {
+            ordinal,
+            name: device_name(device)?,
+            total_memory: device_total_memory(device)?,
+            compute_capability: (major, minor),
+            device,
+        })
+    }
+    /// List all the GPUs.
+    pub fn list() -> Result<Vec<Self>, CUerror> {
+        let mut count = 0;
+        unsafe {
+            cuInit(0)?;
+            cuDeviceGetCount(&mut count)?;
+        }
+        (0..count).map(Self::get).collect()
+    }
+    /// The device pinned by the `CUDA_MIN_DEVICE` environment variable, if any.
+    /// Returns CUDA_ERROR_INVALID_DEVICE if the variable is not a valid ordinal.
+    pub fn from_env() -> Result<Option<Self>, CUerror> {
+        match std::env::var(DEVICE_ENV) {
+            Ok(ordinal) => {
+                let ordinal = ordinal
+                    .trim()
+                    .parse()
+                    .map_err(|_| CUerror::INVALID_DEVICE)?;
+                Self::get(ordinal).map(Some)
+            }
+            Err(_) => Ok(None),
+        }
+    }
+    /// Compute capability as a number, e.g., 86 for `sm_86`.
+    pub fn sm(&self) -> i32 {
+        self.compute_capability.0 * 10 + self.compute_capability.1
+    }
+    /// Select the first GPU whose compute capability is at least `sm` (e.g., 80 for `sm_80`).
+    /// If `CUDA_MIN_DEVICE` is set, only the pinned GPU is considered, and CUDA_ERROR_INVALID_DEVICE is returned if it is too old.
+    pub fn select_min_sm(sm: i32) -> Result<Self, CUerror> {
+        if let Some(info) = Self::from_env()? {
+            return if info.sm() >= sm {
+                Ok(info)
+            } else {
+                Err(CUerror::INVALID_DEVICE)
+            };
+        }
+        Self::list()?
+            .into_iter()
+            .find(|info| info.sm() >= sm)
+            .ok_or(CUerror::NO_DEVICE)
+    }
+    /// Init this GPU with its primary context.
+    pub fn open(&self) -> Result<Device, CUerror> {
+        Device::from_raw(self.device, None)
+    }
+}
+fn device_name(device: CUdevice) -> Result<String, CUerror> {
+    let mut name = [0 as c_char; 256];
+    unsafe {
+        cuDeviceGetName(name.as_mut_ptr(), name.len() as c_int, device)?;
+        Ok(CStr::from_ptr(name.as_ptr()).to_string_lossy().into_owned())
+    }
+}
+fn device_total_memory(device: CUdevice) -> Result<usize, CUerror> {
+    let mut bytes = 0;
+    unsafe { cuDeviceTotalMem(&mut bytes, device)? }
+    Ok(bytes)
+}
+/// Context creation flags, could be combined with `|`.
+///
+/// At most one of the scheduling modes (`SCHED_*`) should be set, which controls how the host thread waits for the GPU.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ContextFlags(pub c_uint);
+impl ContextFlags {
+    /// CU_CTX_SCHED_AUTO, spin if there are more cores than active contexts, otherwise yield.
+    pub const SCHED_AUTO: Self = Self(0);
+    /// CU_CTX_SCHED_SPIN, actively spin while waiting, lowest latency but occupies a CPU core.
+    pub const SCHED_SPIN: Self = Self(1);
+    /// CU_CTX_SCHED_YIELD, yield the thread while waiting.
+    pub const SCHED_YIELD: Self = Self(2);
+    /// CU_CTX_SCHED_BLOCKING_SYNC, block the thread on a synchronization primitive while waiting.
+    pub const SCHED_BLOCKING_SYNC: Self = Self(4);
+    /// CU_CTX_MAP_HOST, allow mapping pinned host memory into the device address space.
+    pub const MAP_HOST: Self = Self(8);
+    /// CU_CTX_LMEM_RESIZE_TO_MAX, keep local memory allocated after resizing it for a kernel.
+    pub const LMEM_RESIZE_TO_MAX: Self = Self(0x10);
+}
+impl BitOr for ContextFlags {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+/// Primary contexts retained by this process, as (device, context, number of `Device`s using it).
+///
+/// Each primary context is retained from the driver only once, and released when the last `Device` using it is dropped.
+static PRIMARY_CONTEXTS: Mutex<Vec<(c_int, usize, usize)>> = Mutex::new(Vec::new());
+/// Keeps the context of a `Device` current on the calling thread, see `Device::bind`.
+///
+/// It pops the context on drop, thus it must be dropped on the thread creating it.
+#[derive(Debug)]
+pub struct ContextGuard<'d> {
+    _marker: PhantomData<(&'d Device, *mut ())>,
+}
+impl Drop for ContextGuard<'_> {
+    fn drop(&mut self) {
+        let mut ctx = CUcontext(ptr::null_mut());
+        let _ = unsafe { cuCtxPopCurrent(&mut ctx) };
+    }
+}
 /// Cuda device and context
+///
+/// It is `Send + Sync`: every method (and every method of the modules, functions, buffers, streams and events created
+/// from it) binds the context on the calling thread first, thus one device could be shared by a thread pool.
+#[derive(Debug)]
 pub struct Device {
-    #[allow(dead_code)]
     device: CUdevice,
-    #[allow(dead_code)]
     context: CUcontext,
+    /// whether `context` is the retained primary context rather than an owned one.
+    primary: bool,
+    cache: Mutex<AllocCache>,
 }
 impl Drop for Device {
     fn drop(&mut self) {
-        unsafe { cuCtxDestroy(self.context).unwrap() }
+        let _ = self.trim();
+        if self.primary {
+            let mut contexts = PRIMARY_CONTEXTS.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(i) = contexts.iter().position(|entry| entry.0 == self.device.0) {
+                contexts[i].2 -= 1;
+                if contexts[i].2 == 0 {
+                    contexts.swap_remove(i);
+                    unsafe { cuDevicePrimaryCtxRelease(self.device).unwrap() }
+                }
+            }
+        } else {
+            unsafe { cuCtxDestroy(self.context).unwrap() }
+        }
+    }
+}
+/// Statistics of the caching allocator of a `Device`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Allocations served by a cached block.
+    pub hits: u64,
+    /// Allocations that had to call `cuMemAlloc`.
+    pub misses: u64,
+    /// Bytes of freed blocks kept for reuse.
+    pub bytes_cached: usize,
+}
+/// Freed device blocks, bucketed by their sizes (powers of two).
+#[derive(Debug, Default)]
+struct AllocCache {
+    free: HashMap<usize, Vec<*mut c_void>>,
+    stats: CacheStats,
+}
+// The cached blocks are device addresses, which are never dereferenced on the host.
+unsafe impl Send for AllocCache {}
+const _ASSERT_THREAD_SAFE: fn() = || {
+    fn assert<T: Send + Sync>() {}
+    assert::<Device>();
+    assert::<Module<'_>>();
+    assert::<Function<'_>>();
+    assert::<DeviceBuffer<'_, u32>>();
+    assert::<ManagedBuffer<'_, u32>>();
+    assert::<PinnedBuffer<'_, u32>>();
+    assert::<Stream<'_>>();
+    assert::<Event<'_>>();
+    assert::<MemPool<'_>>();
+};
+impl AllocCache {
+    /// Blocks up to 2 MiB are rounded up to powers of two (at least 512 bytes), thus similar sizes could share blocks,
+    /// while larger blocks are rounded up to multiples of 2 MiB only, which wastes less than 2 MiB per block.
+    fn bucket(size: usize) -> usize {
+        const LARGE: usize = 2 << 20;
+        if size <= LARGE {
+            size.max(512).next_power_of_two()
+        } else {
+            size.next_multiple_of(LARGE)
+        }
     }
 }
 impl Device {
     const STREAM: CUstream = CUstream(ptr::null_mut()); // default null stream
-    /// the very fast approach to init first device and context, panic if init procedure contains errors.
-    /// If you want to init more than the default GPU, use `init_all` instead.
+    /// the very fast approach to init first device (or the one pinned by `CUDA_MIN_DEVICE`) and context, panic if init procedure contains errors.
+    /// If you want to init more than the default GPU, use `init_all` instead, `DeviceInfo` helps choosing a GPU.
+    ///
+    /// The primary context of the device is used, thus calling it twice (or mixing it with other CUDA libraries) shares the same context.
     pub fn init() -> Self {
-        let mut device = CUdevice(0);
+        let ordinal = DeviceInfo::from_env()
+            .unwrap()
+            .map_or(0, |info| info.ordinal);
+        Self::with_ordinal(ordinal).unwrap()
+    }
+    /// Init the device of `ordinal` with its primary context.
+    pub fn with_ordinal(ordinal: i32) -> Result<Self, CUerror> {
+        Self::from_raw(Self::get_raw(ordinal)?, None)
+    }
+    /// Init the GPU with the most free memory, or the one pinned by `CUDA_MIN_DEVICE`.
+    ///
+    /// The driver could only answer free memory with a context, thus the primary context of every GPU is retained
+    /// (which takes some memory of each GPU) while comparing, and released afterwards except for the selected one.
+    /// Use `DeviceInfo::list` or `DeviceInfo::select_min_sm` to select a GPU without creating contexts.
+    pub fn with_most_free_memory() -> Result<Self, CUerror> {
+        if let Some(info) = DeviceInfo::from_env()? {
+            return info.open();
+        }
+        let mut best: Option<(Self, usize)> = None;
+        for info in DeviceInfo::list()? {
+            let device = info.open()?;
+            let free = device.free_memory()?;
+            if best.as_ref().is_none_or(|&(_, most)| free > most) {
+                best = Some((device, free));
+            }
+        }
+        best.map(|(device, _)| device).ok_or(CUerror::NO_DEVICE)
+    }
+    /// Init the device of `ordinal` with its primary context, and set the flags of the primary context.
+    /// Since the primary context is shared, the flags affect every user of it in this process.
+    pub fn primary_with_flags(ordinal: i32, flags: ContextFlags) -> Result<Self, CUerror> {
+        Self::from_raw(Self::get_raw(ordinal)?, Some(flags))
+    }
+    /// Create a new context owned by this `Device` on the device of `ordinal`, which is destroyed on drop.
+    /// Buffers allocated in it are invalid in other contexts, prefer the primary context unless isolation is necessary.
+    pub fn new_context(ordinal: i32, flags: ContextFlags) -> Result<Self, CUerror> {
+        let device = Self::get_raw(ordinal)?;
         let mut ctx = CUcontext(ptr::null_mut());
         unsafe {
-            cuInit(0).unwrap();
-            cuDeviceGet(&mut device, 0).unwrap();
-            cuCtxCreate(&mut ctx, 0, device).unwrap();
-            cuCtxSetCurrent(ctx).unwrap();
-            // cuCtxSetLimit(1, 1024 * 1024).unwrap();
+            cuCtxCreate(&mut ctx, flags.0, device)?;
+            cuCtxSetCurrent(ctx)?;
+            // cuCtxSetLimit(1, 1024 * 1024)?;
         }
-        Self {
+        Ok(Self {
             device,
             context: ctx,
+            primary: false,
+            cache: Mutex::default(),
+        })
+    }
+    fn get_raw(ordinal: i32) -> Result<CUdevice, CUerror> {
+        let mut device = CUdevice(0);
+        unsafe {
+            cuInit(0)?;
+            cuDeviceGet(&mut device, ordinal)?;
+        }
+        Ok(device)
+    }
+    /// Retain the primary context of `device` through `PRIMARY_CONTEXTS`.
+    fn from_raw(device: CUdevice, flags: Option<ContextFlags>) -> Result<Self, CUerror> {
+        let mut contexts = PRIMARY_CONTEXTS.lock().unwrap_or_else(|e| e.into_inner());
+        unsafe {
+            if let Some(flags) = flags {
+                cuDevicePrimaryCtxSetFlags(device, flags.0)?;
+            }
+            let ctx = if let Some(entry) = contexts.iter_mut().find(|entry| entry.0 == device.0) {
+                entry.2 += 1;
+                CUcontext(entry.1 as _)
+            } else {
+                let mut ctx = CUcontext(ptr::null_mut());
+                cuDevicePrimaryCtxRetain(&mut ctx, device)?;
+                contexts.push((device.0, ctx.0 as usize, 1));
+                ctx
+            };
+            cuCtxSetCurrent(ctx)?;
+            Ok(Self {
+                device,
+                context: ctx,
+                primary: true,
+                cache: Mutex::default(),
+            })
+        }
+    }
+    /// Push the context of this device on the calling thread, until the returned guard is dropped.
+    /// Methods of this crate bind the context by themselves, it is only necessary for calling the driver API directly.
+    pub fn bind(&self) -> Result<ContextGuard<'_>, CUerror> {
+        unsafe { cuCtxPushCurrent(self.context)? }
+        Ok(ContextGuard {
+            _marker: PhantomData,
+        })
+    }
+    /// Whether this `Device` uses the primary context of the GPU.
+    pub fn is_primary(&self) -> bool {
+        self.primary
+    }
+    /// Flags of the context, including the scheduling mode.
+    pub fn context_flags(&self) -> Result<ContextFlags, CUerror> {
+        let mut flags = 0;
+        unsafe {
+            if self.primary {
+                let mut active = 0;
+                cuDevicePrimaryCtxGetState(self.device, &mut flags, &mut active)?;
+            } else {
+                let _ctx = self.bind()?;
+                cuCtxGetFlags(&mut flags)?;
+            }
         }
+        Ok(ContextFlags(flags))
     }
-    /// Init all GPUs. In case CUerror generates, return an error.
+    /// Init all GPUs with their primary contexts. In case CUerror generates, return an error.
     pub fn init_all() -> Result<Vec<Self>, CUerror> {
         unsafe {
             cuInit(0)?; // Initialize the CUDA driver API Initializes the driver API and must be called before any other function from the driver API in the current process. Currently, the Flags parameter must be 0. If cuInit() has not been called, any function from the driver API will return CUDA_ERROR_NOT_INITIALIZED.
@@ -202,31 +1222,96 @@ impl Device {
             let mut res = Vec::with_capacity(count as usize);
             for i in 0..count {
                 let mut device = CUdevice(0);
-                let mut ctx = CUcontext(ptr::null_mut());
                 cuDeviceGet(&mut device, i)?;
-                cuCtxCreate(&mut ctx, 0, device)?;
-                cuCtxSetCurrent(ctx)?;
-                // cuCtxSetLimit(1, 1024 * 1024)?;
-                res.push(Self {
-                    device,
-                    context: ctx,
-                });
+                res.push(Self::from_raw(device, None)?);
             }
             Ok(res)
         }
     }
     /// Get major and minor CUDA capability version to calculate sm_** for generating better code.
     pub fn get_native_target_cpu_param(&self) -> Result<(c_int, c_int), CUerror> {
-        let mut major = 0;
-        let mut minor = 0;
+        Ok((
+            self.get_attribute(DeviceAttribute::ComputeCapabilityMajor)?,
+            self.get_attribute(DeviceAttribute::ComputeCapabilityMinor)?,
+        ))
+    }
+    /// Get a device attribute.
+    pub fn get_attribute(&self, attrib: DeviceAttribute) -> Result<c_int, CUerror> {
+        self.get_attribute_raw(attrib as c_int)
+    }
+    /// Name of the device, e.g., "NVIDIA GeForce RTX 4090".
+    pub fn name(&self) -> Result<String, CUerror> {
+        device_name(self.device)
+    }
+    /// Total memory of the device in bytes.
+    pub fn total_memory(&self) -> Result<usize, CUerror> {
+        device_total_memory(self.device)
+    }
+    /// Free memory of the device in bytes, queried in the context of this `Device`.
+    pub fn free_memory(&self) -> Result<usize, CUerror> {
+        let _ctx = self.bind()?;
+        let mut free = 0;
+        let mut total = 0;
+        unsafe { cuMemGetInfo(&mut free, &mut total)? }
+        Ok(free)
+    }
+    /// 16-byte UUID of the device, which is stable across processes, unlike the ordinal.
+    pub fn uuid(&self) -> Result<[u8; 16], CUerror> {
+        let mut uuid = [0; 16];
+        unsafe { cuDeviceGetUuid(&mut uuid, self.device)? }
+        Ok(uuid)
+    }
+    /// PCI bus id of the device, in the format of `[domain]:[bus]:[device].[function]`.
+    pub fn pci_bus_id(&self) -> Result<String, CUerror> {
+        // 13 bytes are enough according to the document, 32 for safety.
+        let mut id = [0 as c_char; 32];
         unsafe {
-            // According to https://docs.nvidia.com/cuda/cuda-driver-api/group__CUDA__TYPES.html
-            // CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MAJOR = 75
-            // CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MINOR = 76
-            cuDeviceGetAttribute(&mut major, 75, self.device)?;
-            cuDeviceGetAttribute(&mut minor, 76, self.device)?;
+            cuDeviceGetPCIBusId(id.as_mut_ptr(), id.len() as c_int, self.device)?;
+            Ok(CStr::from_ptr(id.as_ptr()).to_string_lossy().into_owned())
         }
-        Ok((major, minor))
+    }
+    /// Query a snapshot of the device properties, which could be printed or (with the `serde` feature) serialized.
+    pub fn properties(&self) -> Result<DeviceProperties, CUerror> {
+        use DeviceAttribute::*;
+        let attr = |attrib| self.get_attribute(attrib);
+        Ok(DeviceProperties {
+            name: self.name()?,
+            uuid: self.uuid()?,
+            pci_bus_id: self.pci_bus_id()?,
+            compute_capability: self.get_native_target_cpu_param()?,
+            total_memory: self.total_memory()?,
+            multiprocessor_count: attr(MultiprocessorCount)?,
+            warp_size: attr(WarpSize)?,
+            max_threads_per_block: attr(MaxThreadsPerBlock)?,
+            max_threads_per_multiprocessor: attr(MaxThreadsPerMultiprocessor)?,
+            max_block_dim: [
+                attr(MaxBlockDimX)?,
+                attr(MaxBlockDimY)?,
+                attr(MaxBlockDimZ)?,
+            ],
+            max_grid_dim: [attr(MaxGridDimX)?, attr(MaxGridDimY)?, attr(MaxGridDimZ)?],
+            max_shared_memory_per_block: attr(MaxSharedMemoryPerBlock)?,
+            max_shared_memory_per_block_optin: attr(MaxSharedMemoryPerBlockOptin)?,
+            max_shared_memory_per_multiprocessor: attr(MaxSharedMemoryPerMultiprocessor)?,
+            max_registers_per_block: attr(MaxRegistersPerBlock)?,
+            total_constant_memory: attr(TotalConstantMemory)?,
+            l2_cache_size: attr(L2CacheSize)?,
+            clock_rate: attr(ClockRate)?,
+            memory_clock_rate: attr(MemoryClockRate)?,
+            global_memory_bus_width: attr(GlobalMemoryBusWidth)?,
+            async_engine_count: attr(AsyncEngineCount)?,
+            integrated: attr(Integrated)? != 0,
+            ecc_enabled: attr(EccEnabled)? != 0,
+            unified_addressing: attr(UnifiedAddressing)? != 0,
+            managed_memory: attr(ManagedMemory)? != 0,
+            concurrent_managed_access: attr(ConcurrentManagedAccess)? != 0,
+        })
+    }
+    /// Get a device attribute by its raw `CUdevice_attribute` code.
+    pub fn get_attribute_raw(&self, attrib: c_int) -> Result<c_int, CUerror> {
+        let mut value = 0;
+        unsafe { cuDeviceGetAttribute(&mut value, attrib, self.device)? }
+        Ok(value)
     }
     /// Get equivlent of "-Ctarget-cpu=native" for this device
     /// panic if cuDeviceGetAttribute returns an error
@@ -236,7 +1321,7 @@ impl Device {
     }
     /// compile a module. Returns an error code 218 mostly means you do not send the correct PTX code into this function.
     #[must_use = "You should check whether the execution successes."]
-    pub fn load<'a>(&'a self, file: &str) -> Result<CUmodule<'a>, CUerror> {
+    pub fn load<'a>(&'a self, file: &str) -> Result<Module<'a>, CUerror> {
         if let Ok(cstr) = &CString::new(file) {
             self.load_raw(cstr)
         } else {
@@ -245,67 +1330,676 @@ impl Device {
     }
     /// compile a module, with `&CStr` as its input
     #[must_use = "You should check whether the execution successes."]
-    pub fn load_raw<'a>(&'a self, file: &CStr) -> Result<CUmodule<'a>, CUerror> {
+    pub fn load_raw<'a>(&'a self, file: &CStr) -> Result<Module<'a>, CUerror> {
         let mut module = CUmodule(ptr::null_mut(), PhantomData);
+        let _ctx = self.bind()?;
         unsafe { cuModuleLoad(&mut module, file.as_ptr() as _)? }
-        Ok(module)
+        Ok(Module {
+            module,
+            device: self,
+            info_log: String::new(),
+            wall_time: Duration::ZERO,
+        })
     }
-    /// compile a module. Returns an error code 218 mostly means you do not send the correct PTX code into this function.
+    /// compile a module. Returns an error code 218 mostly means you do not send the correct PTX code into this function,
+    /// the JIT error log in `CompileError` tells what is wrong.
     #[must_use = "You should check whether the execution successes."]
-    pub fn compile<'a>(&'a self, ptx: &str) -> Result<CUmodule<'a>, CUerror> {
-        if let Ok(cstr) = &CString::new(ptx) {
-            self.compile_raw(cstr)
-        } else {
-            Err(CUerror(NonZero::new(218).unwrap()))
-        }
+    pub fn compile<'a>(&'a self, ptx: &str) -> Result<Module<'a>, CompileError> {
+        self.compile_with(ptx, &CompileOptions::new())
     }
     /// compile a module, with `&CStr` as its input
     #[must_use = "You should check whether the execution successes."]
-    pub fn compile_raw<'a>(&'a self, c_ptx: &CStr) -> Result<CUmodule<'a>, CUerror> {
-        let mut module = CUmodule(ptr::null_mut(), PhantomData);
-        unsafe { cuModuleLoadData(&mut module, c_ptx.as_ptr() as _)? }
-        Ok(module)
-    }
-    #[must_use = "You should check whether the execution successes."]
-    pub fn set_print_buffer(size: usize) -> CUresult {
-        unsafe { cuCtxSetLimit(1, size) }
+    pub fn compile_raw<'a>(&'a self, c_ptx: &CStr) -> Result<Module<'a>, CompileError> {
+        self.compile_raw_with(c_ptx, &CompileOptions::new())
     }
-}
-impl<'a> CUmodule<'a> {
-    /// Get `CUfunction` from a module.
+    /// compile a module with JIT options.
     #[must_use = "You should check whether the execution successes."]
-    pub fn get_function<'b>(self, ptx: &str) -> Result<CUfunction<'b>, CUerror>
-    where
-        'a: 'b,
-    {
+    pub fn compile_with<'a>(
+        &'a self,
+        ptx: &str,
+        compile_options: &CompileOptions,
+    ) -> Result<Module<'a>, CompileError> {
         if let Ok(cstr) = &CString::new(ptx) {
-            self.get_function_raw(cstr)
+            self.compile_raw_with(cstr, compile_options)
         } else {
-            Err(CUerror(NonZero::new(218).unwrap()))
+            Err(CompileError {
+                error: CUerror(NonZero::new(218).unwrap()),
+                log: "PTX code should not contain any nul byte".into(),
+            })
         }
     }
-    /// Get `CUfunction` from a module, with `&CStr` as its input.
+    /// compile a module with JIT options, with `&CStr` as its input
     #[must_use = "You should check whether the execution successes."]
-    pub fn get_function_raw<'b>(self, function_name: &CStr) -> Result<CUfunction<'b>, CUerror>
-    where
-        'a: 'b,
-    {
-        let mut function = CUfunction(ptr::null_mut(), PhantomData);
-        unsafe { cuModuleGetFunction(&mut function, self, function_name.as_ptr())? }
-        Ok(function)
-    }
-}
-
-impl<'b> CUfunction<'b> {
-    /// Get major and minor CUDA capability version to calculate sm_** for generating better code.
-    pub fn get_max_thread_per_block(&self) -> Result<c_int, CUerror> {
-        let mut max_thread = 0;
+    pub fn compile_raw_with<'a>(
+        &'a self,
+        c_ptx: &CStr,
+        compile_options: &CompileOptions,
+    ) -> Result<Module<'a>, CompileError> {
+        let mut module = CUmodule(ptr::null_mut(), PhantomData);
+        let mut logs = JitLogs::new();
+        let mut options = Vec::new();
+        let mut values = Vec::new();
+        logs.push_options(&mut options, &mut values);
+        compile_options.push_options(&mut options, &mut values);
+        let wall_time = push_wall_time_option(&mut options, &mut values);
+        let _ctx = self.bind().map_err(|error| CompileError {
+            error,
+            log: String::new(),
+        })?;
         unsafe {
-            // According to https://docs.nvidia.com/cuda/cuda-driver-api/group__CUDA__TYPES.html
-            // CU_FUNC_ATTRIBUTE_MAX_THREAD_PER_BLOCK = 0
-            cuFuncGetAttribute(&mut max_thread, 0, *self)?;
+            cuModuleLoadDataEx(
+                &mut module,
+                c_ptx.as_ptr() as _,
+                options.len() as c_uint,
+                options.as_mut_ptr(),
+                values.as_mut_ptr(),
+            )
         }
-        Ok(max_thread)
+        .map_err(|error| CompileError {
+            error,
+            log: logs.error(),
+        })?;
+        Ok(Module {
+            module,
+            device: self,
+            info_log: logs.info(),
+            wall_time: read_wall_time(&values, wall_time),
+        })
+    }
+    /// Load a module from an in-memory image, whose kind (PTX, cubin or fatbin) is detected by its header.
+    /// Ahead-of-time compiled cubin/fatbin skip the JIT, e.g., `device.load_image(include_bytes!("kernel.cubin"))`.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn load_image<'a>(&'a self, image: &[u8]) -> Result<Module<'a>, CompileError> {
+        if ImageKind::is_fatbin_wrapper(image) {
+            // CUDA_ERROR_INVALID_IMAGE = 200
+            return Err(CompileError {
+                error: CUerror(NonZero::new(200).unwrap()),
+                log: "image is a fatbin wrapper rather than a fatbin, load the fatbin it points to instead".into(),
+            });
+        }
+        let kind = ImageKind::detect(image);
+        if kind == ImageKind::Ptx {
+            let ptx = image.strip_suffix(&[0]).unwrap_or(image);
+            return match CString::new(ptx) {
+                Ok(cstr) => self.compile_raw(&cstr),
+                Err(_) => Err(CompileError {
+                    error: CUerror(NonZero::new(218).unwrap()),
+                    log: "image is neither cubin nor fatbin, and PTX code should not contain any nul byte".into(),
+                }),
+            };
+        }
+        // copy into an 8-byte aligned buffer, since `include_bytes!` gives no alignment guarantee.
+        let mut aligned = vec![0u64; image.len().div_ceil(8)];
+        unsafe {
+            ptr::copy_nonoverlapping(image.as_ptr(), aligned.as_mut_ptr() as *mut u8, image.len());
+        }
+        let mut module = CUmodule(ptr::null_mut(), PhantomData);
+        let _ctx = self.bind().map_err(|error| CompileError {
+            error,
+            log: String::new(),
+        })?;
+        unsafe {
+            if kind == ImageKind::Fatbin {
+                cuModuleLoadFatBinary(&mut module, aligned.as_ptr() as _)
+            } else {
+                cuModuleLoadData(&mut module, aligned.as_ptr() as _)
+            }
+        }
+        .map_err(|error| CompileError {
+            error,
+            log: String::new(),
+        })?;
+        Ok(Module {
+            module,
+            device: self,
+            info_log: String::new(),
+            wall_time: Duration::ZERO,
+        })
+    }
+    /// Stream priority range as `(least, greatest)`, where greatest is numerically the smallest.
+    pub fn stream_priority_range(&self) -> Result<(c_int, c_int), CUerror> {
+        let mut least = 0;
+        let mut greatest = 0;
+        let _ctx = self.bind()?;
+        unsafe { cuCtxGetStreamPriorityRange(&mut least, &mut greatest)? }
+        Ok((least, greatest))
+    }
+    /// Statistics of the caching allocator, which launches use for their temporaries.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.lock().unwrap_or_else(|e| e.into_inner()).stats
+    }
+    /// Release all the cached blocks of the caching allocator.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn trim(&self) -> CUresult {
+        let _ctx = self.bind()?;
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        let mut result = Ok(());
+        for ptr in cache.free.drain().flat_map(|(_, blocks)| blocks) {
+            result = result.and(unsafe { cuMemFree(ptr) });
+        }
+        cache.stats.bytes_cached = 0;
+        result
+    }
+    /// Get a block of at least `size` bytes from the cache, or allocate a new one.
+    fn cache_alloc(&self, size: usize) -> Result<*mut c_void, CUerror> {
+        let bucket = AllocCache::bucket(size);
+        {
+            let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(ptr) = cache.free.get_mut(&bucket).and_then(Vec::pop) {
+                cache.stats.hits += 1;
+                cache.stats.bytes_cached -= bucket;
+                return Ok(ptr);
+            }
+            cache.stats.misses += 1;
+        }
+        let mut ptr = ptr::null_mut();
+        let _ctx = self.bind()?;
+        self.retry_on_oom(|| unsafe { cuMemAlloc(&mut ptr, bucket) })?;
+        Ok(ptr)
+    }
+    /// Run the allocation `alloc`, on CUDA_ERROR_OUT_OF_MEMORY release the cached blocks (which might be
+    /// what occupies the memory) and retry once.
+    fn retry_on_oom(&self, mut alloc: impl FnMut() -> CUresult) -> CUresult {
+        match alloc() {
+            Err(e) if e.code() == 2 => {
+                self.trim()?;
+                alloc()
+            }
+            result => result,
+        }
+    }
+    /// Return a block allocated by `cache_alloc` with the same `size`.
+    fn cache_free(&self, ptr: *mut c_void, size: usize) {
+        let bucket = AllocCache::bucket(size);
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        cache.free.entry(bucket).or_default().push(ptr);
+        cache.stats.bytes_cached += bucket;
+    }
+    /// Whether kernels on this device could access the memory of `peer` directly (P2P over NVLink or PCIe).
+    pub fn can_access_peer(&self, peer: &Device) -> Result<bool, CUerror> {
+        if self.device == peer.device {
+            return Ok(true);
+        }
+        let mut can_access = 0;
+        unsafe { cuDeviceCanAccessPeer(&mut can_access, self.device, peer.device)? }
+        Ok(can_access != 0)
+    }
+    /// Allow this device to access the memory of `peer`, which makes copies between them (and kernels reading
+    /// pointers of `peer`) go through P2P. Access is one-directional, enable it on both devices if necessary.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn enable_peer_access(&self, peer: &Device) -> CUresult {
+        if self.context.0 == peer.context.0 {
+            return Ok(());
+        }
+        let _ctx = self.bind()?;
+        match unsafe { cuCtxEnablePeerAccess(peer.context, 0) } {
+            // CUDA_ERROR_PEER_ACCESS_ALREADY_ENABLED
+            Err(e) if e.code() == 704 => Ok(()),
+            result => result,
+        }
+    }
+    /// Revoke the access enabled by `enable_peer_access`.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn disable_peer_access(&self, peer: &Device) -> CUresult {
+        let _ctx = self.bind()?;
+        unsafe { cuCtxDisablePeerAccess(peer.context) }
+    }
+    /// Set the size of the `vprintf` buffer of the context current on the calling thread, which may not be this device.
+    #[deprecated(
+        since = "0.1.5",
+        note = "It operates on the current context only, use `device.set_print_buffer_size(size)` instead."
+    )]
+    #[must_use = "You should check whether the execution successes."]
+    pub fn set_print_buffer(size: usize) -> CUresult {
+        unsafe { cuCtxSetLimit(1, size) }
+    }
+    /// Set the size of the `vprintf` buffer of this device.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn set_print_buffer_size(&self, size: usize) -> CUresult {
+        let _ctx = self.bind()?;
+        // CU_LIMIT_PRINTF_FIFO_SIZE = 1
+        unsafe { cuCtxSetLimit(1, size) }
+    }
+}
+const JIT_LOG_SIZE: usize = 16384;
+/// Log buffers sent to the JIT, filled by the driver while compiling.
+struct JitLogs {
+    info: Vec<u8>,
+    error: Vec<u8>,
+}
+impl JitLogs {
+    fn new() -> Self {
+        Self {
+            info: vec![0; JIT_LOG_SIZE],
+            error: vec![0; JIT_LOG_SIZE],
+        }
+    }
+    /// Append log options, `self` must be kept until the driver call returns.
+    fn push_options(&mut self, options: &mut Vec<c_int>, values: &mut Vec<*mut c_void>) {
+        // According to https://docs.nvidia.com/cuda/cuda-driver-api/group__CUDA__TYPES.html
+        // CU_JIT_INFO_LOG_BUFFER = 3, CU_JIT_INFO_LOG_BUFFER_SIZE_BYTES = 4,
+        // CU_JIT_ERROR_LOG_BUFFER = 5, CU_JIT_ERROR_LOG_BUFFER_SIZE_BYTES = 6, CU_JIT_LOG_VERBOSE = 12
+        options.extend([3, 4, 5, 6, 12]);
+        values.extend([
+            self.info.as_mut_ptr() as _,
+            ptr::without_provenance_mut(JIT_LOG_SIZE),
+            self.error.as_mut_ptr() as _,
+            ptr::without_provenance_mut(JIT_LOG_SIZE),
+            ptr::without_provenance_mut(1),
+        ]);
+    }
+    fn read(buffer: &[u8]) -> String {
+        match CStr::from_bytes_until_nul(buffer) {
+            Ok(log) => log.to_string_lossy().trim_end().into(),
+            Err(_) => String::from_utf8_lossy(buffer).trim_end().into(),
+        }
+    }
+    fn info(&self) -> String {
+        Self::read(&self.info)
+    }
+    fn error(&self) -> String {
+        Self::read(&self.error)
+    }
+}
+/// Kind of a module image.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ImageKind {
+    /// PTX text, compiled by the JIT.
+    Ptx,
+    /// ELF cubin, compiled for a specific architecture.
+    Cubin,
+    /// Fat binary, which may contain cubins for several architectures and PTX.
+    Fatbin,
+}
+impl ImageKind {
+    /// Detect the kind of `image` by its magic header, images without known headers are regarded as PTX.
+    pub fn detect(image: &[u8]) -> Self {
+        const ELF_MAGIC: [u8; 4] = *b"\x7fELF";
+        const FATBIN_MAGIC: u32 = 0xba55ed50;
+        match image.first_chunk::<4>() {
+            Some(&ELF_MAGIC) => Self::Cubin,
+            Some(&magic) if u32::from_le_bytes(magic) == FATBIN_MAGIC => Self::Fatbin,
+            _ => Self::Ptx,
+        }
+    }
+    /// Whether `image` is a `__fatBinC_Wrapper_t` (emitted into host objects by nvcc), which holds a pointer
+    /// to the fatbin in the process creating it rather than the fatbin itself, thus could not be loaded.
+    fn is_fatbin_wrapper(image: &[u8]) -> bool {
+        const FATBIN_WRAPPER_MAGIC: u32 = 0x466243b1;
+        image
+            .first_chunk::<4>()
+            .is_some_and(|&magic| u32::from_le_bytes(magic) == FATBIN_WRAPPER_MAGIC)
+    }
+}
+/// Append CU_JIT_WALL_TIME (= 2), returns the index of its value, which the driver overwrites with a `f32` in milliseconds.
+fn push_wall_time_option(options: &mut Vec<c_int>, values: &mut Vec<*mut c_void>) -> usize {
+    options.push(2);
+    values.push(ptr::null_mut());
+    values.len() - 1
+}
+fn read_wall_time(values: &[*mut c_void], index: usize) -> Duration {
+    let milliseconds = f32::from_bits(values[index].addr() as u32);
+    Duration::try_from_secs_f32(milliseconds / 1000.0).unwrap_or_default()
+}
+/// Which code the driver prefers when both PTX and a matching binary are available.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FallbackStrategy {
+    /// CU_PREFER_PTX
+    PreferPtx = 0,
+    /// CU_PREFER_BINARY
+    PreferBinary = 1,
+}
+/// JIT options for `Device::compile_with`, unset options are left to the driver.
+#[derive(Clone, Debug, Default)]
+pub struct CompileOptions {
+    /// Optimization level of ptxas, 0 to 4, the driver uses 4 by default.
+    pub optimization_level: Option<u32>,
+    /// Maximum number of registers a thread may use.
+    pub max_registers: Option<u32>,
+    /// Compile for `sm_{target}` (e.g., 86 for sm_86) rather than the architecture of the current context.
+    pub target: Option<u32>,
+    /// Minimum number of threads per block the compiled code must support, could not be combined with `target`.
+    pub threads_per_block: Option<u32>,
+    /// Generate line-number information for profilers.
+    pub generate_line_info: bool,
+    /// Which code to choose when both PTX and a binary are available.
+    pub fallback: Option<FallbackStrategy>,
+}
+impl CompileOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn optimization_level(mut self, level: u32) -> Self {
+        self.optimization_level = Some(level);
+        self
+    }
+    pub fn max_registers(mut self, registers: u32) -> Self {
+        self.max_registers = Some(registers);
+        self
+    }
+    pub fn target(mut self, sm: u32) -> Self {
+        self.target = Some(sm);
+        self
+    }
+    pub fn threads_per_block(mut self, threads: u32) -> Self {
+        self.threads_per_block = Some(threads);
+        self
+    }
+    pub fn generate_line_info(mut self) -> Self {
+        self.generate_line_info = true;
+        self
+    }
+    pub fn fallback(mut self, fallback: FallbackStrategy) -> Self {
+        self.fallback = Some(fallback);
+        self
+    }
+    /// Append the options that are set, all of them are passed by value.
+    fn push_options(&self, options: &mut Vec<c_int>, values: &mut Vec<*mut c_void>) {
+        // According to https://docs.nvidia.com/cuda/cuda-driver-api/group__CUDA__TYPES.html
+        // CU_JIT_MAX_REGISTERS = 0, CU_JIT_THREADS_PER_BLOCK = 1, CU_JIT_OPTIMIZATION_LEVEL = 7,
+        // CU_JIT_TARGET = 9, CU_JIT_FALLBACK_STRATEGY = 10, CU_JIT_GENERATE_LINE_INFO = 13
+        let mut push = |option, value: u32| {
+            options.push(option);
+            values.push(ptr::without_provenance_mut(value as usize));
+        };
+        if let Some(registers) = self.max_registers {
+            push(0, registers)
+        }
+        if let Some(threads) = self.threads_per_block {
+            push(1, threads)
+        }
+        if let Some(level) = self.optimization_level {
+            push(7, level)
+        }
+        if let Some(sm) = self.target {
+            push(9, sm)
+        }
+        if let Some(fallback) = self.fallback {
+            push(10, fallback as u32)
+        }
+        if self.generate_line_info {
+            push(13, 1)
+        }
+    }
+}
+/// Loaded module, unloaded with `cuModuleUnload` on drop.
+/// Functions borrow it, thus they could never outlive the module they come from.
+#[derive(Debug)]
+pub struct Module<'d> {
+    module: CUmodule<'d>,
+    device: &'d Device,
+    info_log: String,
+    wall_time: Duration,
+}
+impl Drop for Module<'_> {
+    fn drop(&mut self) {
+        let _ctx = self.device.bind();
+        let _ = unsafe { cuModuleUnload(self.module) };
+    }
+}
+impl<'d> Module<'d> {
+    /// JIT info log of compiling this module (e.g., registers used and spills), empty if it is not compiled from PTX.
+    pub fn info_log(&self) -> &str {
+        &self.info_log
+    }
+    /// Wall clock time spent in the JIT compiler, zero if it is not compiled from PTX.
+    pub fn jit_wall_time(&self) -> Duration {
+        self.wall_time
+    }
+    /// The raw module handle, which is only valid while this module is alive.
+    pub fn as_raw(&self) -> CUmodule<'d> {
+        self.module
+    }
+    /// Get `Function` from a module.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn get_function(&self, ptx: &str) -> Result<Function<'_>, CUerror> {
+        if let Ok(cstr) = &CString::new(ptx) {
+            self.get_function_raw(cstr)
+        } else {
+            Err(CUerror(NonZero::new(218).unwrap()))
+        }
+    }
+    /// Get `Function` from a module, with `&CStr` as its input.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn get_function_raw(&self, function_name: &CStr) -> Result<Function<'_>, CUerror> {
+        let mut function = CUfunction(ptr::null_mut(), PhantomData);
+        let _ctx = self.device.bind()?;
+        unsafe { cuModuleGetFunction(&mut function, self.module, function_name.as_ptr())? }
+        Ok(Function {
+            function,
+            device: self.device,
+        })
+    }
+    /// Get a module-level variable (a `.global` or `.const` variable in PTX) as `T`.
+    /// The size of `T` must equal the size of the variable, e.g., `[u32; 1024]` for `.b8 TABLE[4096]`.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn global<T>(&self, name: &str) -> Result<Global<'_, T>, CUerror> {
+        if let Ok(cstr) = &CString::new(name) {
+            self.global_raw(cstr)
+        } else {
+            Err(CUerror::INVALID_VALUE)
+        }
+    }
+    /// Get a module-level variable as `T`, with `&CStr` as its input.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn global_raw<T>(&self, name: &CStr) -> Result<Global<'_, T>, CUerror> {
+        let mut ptr = ptr::null_mut();
+        let mut bytes = 0;
+        let _ctx = self.device.bind()?;
+        unsafe { cuModuleGetGlobal(&mut ptr, &mut bytes, self.module, name.as_ptr())? }
+        if bytes != mem::size_of::<T>() {
+            return Err(CUerror::INVALID_VALUE);
+        }
+        Ok(Global {
+            ptr,
+            device: self.device,
+            _marker: PhantomData,
+        })
+    }
+}
+
+/// Typed handle of a module-level variable, which borrows the `Module` it comes from.
+/// Both `.global` and `.const` variables could be read and written from the host.
+#[derive(Debug)]
+pub struct Global<'m, T> {
+    ptr: *mut c_void,
+    device: &'m Device,
+    _marker: PhantomData<T>,
+}
+unsafe impl<T: Send> Send for Global<'_, T> {}
+unsafe impl<T: Sync> Sync for Global<'_, T> {}
+impl<T> Global<'_, T> {
+    /// Device pointer of the variable.
+    pub fn as_raw(&self) -> *mut c_void {
+        self.ptr
+    }
+    /// Copy the variable into host.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn read(&self) -> Result<T, CUerror>
+    where
+        T: Copy,
+    {
+        let mut value = mem::MaybeUninit::<T>::uninit();
+        let _ctx = self.device.bind()?;
+        unsafe {
+            cuMemcpyDtoH(value.as_mut_ptr() as _, self.ptr, mem::size_of::<T>())?;
+            Ok(value.assume_init())
+        }
+    }
+    /// Overwrite the variable, kernels launched afterwards see the new value.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn write(&self, value: &T) -> CUresult {
+        let _ctx = self.device.bind()?;
+        unsafe { cuMemcpyHtoD(self.ptr, value as *const T as _, mem::size_of::<T>()) }
+    }
+}
+
+/// Function attributes, according to <https://docs.nvidia.com/cuda/cuda-driver-api/group__CUDA__TYPES.html> (`CUfunction_attribute`)
+#[repr(i32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FunctionAttribute {
+    /// Maximum number of threads per block, beyond which a launch would fail. Read only.
+    MaxThreadsPerBlock = 0,
+    /// Size in bytes of statically-allocated shared memory. Read only.
+    SharedSizeBytes = 1,
+    /// Size in bytes of user-allocated constant memory. Read only.
+    ConstSizeBytes = 2,
+    /// Size in bytes of local memory used by each thread. Read only.
+    LocalSizeBytes = 3,
+    /// Number of registers used by each thread. Read only.
+    NumRegs = 4,
+    /// PTX virtual architecture version, e.g., 86 for `compute_86`. Read only.
+    PtxVersion = 5,
+    /// Binary architecture version, e.g., 86 for `sm_86`. Read only.
+    BinaryVersion = 6,
+    /// 1 if compiled with `-Xptxas --dlcm=ca`, i.e., global loads are cached in L1. Read only.
+    CacheModeCa = 7,
+    /// Maximum size in bytes of dynamically-allocated shared memory, could be raised above the default 48 KiB.
+    MaxDynamicSharedSizeBytes = 8,
+    /// Preferred shared memory carveout, in percent of the maximum shared memory.
+    PreferredSharedMemoryCarveout = 9,
+}
+/// Preferred split between L1 cache and shared memory, see `Function::set_cache_config`.
+#[repr(i32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FuncCache {
+    PreferNone = 0,
+    PreferShared = 1,
+    PreferL1 = 2,
+    PreferEqual = 3,
+}
+impl<'b> Function<'b> {
+    /// The raw function handle.
+    pub fn as_raw(&self) -> CUfunction<'b> {
+        self.function
+    }
+    /// Get an attribute of this function.
+    pub fn get_attribute(&self, attrib: FunctionAttribute) -> Result<c_int, CUerror> {
+        let mut value = 0;
+        let _ctx = self.device.bind()?;
+        unsafe { cuFuncGetAttribute(&mut value, attrib as c_int, self.function)? }
+        Ok(value)
+    }
+    /// Set an attribute of this function, only `MaxDynamicSharedSizeBytes` and `PreferredSharedMemoryCarveout` are writable.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn set_attribute(&self, attrib: FunctionAttribute, value: c_int) -> CUresult {
+        let _ctx = self.device.bind()?;
+        unsafe { cuFuncSetAttribute(self.function, attrib as c_int, value) }
+    }
+    /// Allow launches with up to `bytes` of dynamic shared memory, which is necessary for more than 48 KiB.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn set_max_dynamic_shared_mem(&self, bytes: u32) -> CUresult {
+        self.set_attribute(FunctionAttribute::MaxDynamicSharedSizeBytes, bytes as c_int)
+    }
+    /// Set the preferred shared memory carveout in percent (0..=100), the rest of the on-chip memory is used as L1 cache.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn set_preferred_carveout(&self, percent: u8) -> CUresult {
+        self.set_attribute(
+            FunctionAttribute::PreferredSharedMemoryCarveout,
+            percent as c_int,
+        )
+    }
+    /// Set the preferred cache configuration, which is only a hint for the driver.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn set_cache_config(&self, config: FuncCache) -> CUresult {
+        let _ctx = self.device.bind()?;
+        unsafe { cuFuncSetCacheConfig(self.function, config as c_int) }
+    }
+    /// Get the maximum number of threads per block, beyond which a launch would fail.
+    pub fn get_max_thread_per_block(&self) -> Result<c_int, CUerror> {
+        self.get_attribute(FunctionAttribute::MaxThreadsPerBlock)
+    }
+    /// Block size maximizing the occupancy with `dynamic_smem` bytes of dynamic shared memory per block,
+    /// returns `(min_grid_size, block_size)`, where `min_grid_size` is the minimum grid size to fill the whole device.
+    /// `block_size_limit` is the largest acceptable block size, 0 means no limit.
+    pub fn max_potential_block_size(
+        &self,
+        dynamic_smem: u32,
+        block_size_limit: u32,
+    ) -> Result<(u32, u32), CUerror> {
+        let mut min_grid_size = 0;
+        let mut block_size = 0;
+        let _ctx = self.device.bind()?;
+        unsafe {
+            cuOccupancyMaxPotentialBlockSize(
+                &mut min_grid_size,
+                &mut block_size,
+                self.function,
+                None,
+                dynamic_smem as usize,
+                block_size_limit as c_int,
+            )?
+        }
+        Ok((min_grid_size as u32, block_size as u32))
+    }
+    /// Number of blocks of `block_size` threads that could be active on each multiprocessor at the same time.
+    pub fn max_active_blocks_per_sm(
+        &self,
+        block_size: u32,
+        dynamic_smem: u32,
+    ) -> Result<u32, CUerror> {
+        let mut num_blocks = 0;
+        let _ctx = self.device.bind()?;
+        unsafe {
+            cuOccupancyMaxActiveBlocksPerMultiprocessor(
+                &mut num_blocks,
+                self.function,
+                block_size as c_int,
+                dynamic_smem as usize,
+            )?
+        }
+        Ok(num_blocks as u32)
+    }
+    /// Kernel node parameters launching `param`, which must contain device memory and values only.
+    /// The returned parameters point into `storage` and `param`, which must outlive their use.
+    fn node_params<R>(
+        &self,
+        param: &Param<'_, R>,
+        storage: &mut Vec<*mut c_void>,
+        args: &mut Vec<*mut c_void>,
+    ) -> Result<CUkernelNodeParams, CUerror> {
+        let &ParamResult::Device(ret, ..) = &param.result else {
+            return Err(CUerror::INVALID_VALUE);
+        };
+        storage.clear();
+        for input in param.input.iter() {
+            match input {
+                &ParamInput::Device(ptr) => storage.push(ptr),
+                ParamInput::Value(_) => storage.push(ptr::null_mut()),
+                ParamInput::Host(..) => return Err(CUerror::INVALID_VALUE),
+            }
+        }
+        storage.push(ret);
+        // the same layout as `stream_call`.
+        *args = storage
+            .iter_mut()
+            .zip(param.input.iter().map(Some).chain([None]))
+            .map(|(x, input)| match input {
+                Some(ParamInput::Value(value)) => value.as_ptr(),
+                _ => x as *mut _ as *mut c_void,
+            })
+            .collect();
+        let (block_size, grid_size) = if param.auto_block {
+            let (_, block) = self.max_potential_block_size(param.shared_mem, 0)?;
+            ((block, 1, 1), (param.grid_len(block), 1, 1))
+        } else {
+            (param.block_size, param.grid_size)
+        };
+        Ok(CUkernelNodeParams {
+            func: self.function.0,
+            grid_dim_x: grid_size.0,
+            grid_dim_y: grid_size.1,
+            grid_dim_z: grid_size.2,
+            block_dim_x: block_size.0,
+            block_dim_y: block_size.1,
+            block_dim_z: block_size.2,
+            shared_mem_bytes: param.shared_mem,
+            kernel_params: args.as_mut_ptr(),
+            extra: ptr::null_mut(),
+            kern: ptr::null_mut(),
+            ctx: self.device.context,
+        })
     }
     /// Call a CUfunction, take care!
     /// SAFETY: You should check very careful since it is a ffi call, and it calls an unsafe function.
@@ -317,18 +2011,33 @@ impl<'b> CUfunction<'b> {
     {
         self.stream_call(param, Device::STREAM)
     }
+    /// Call a CUfunction on `stream`, the returned `PendingResult` syncs that stream only and cannot outlive it.
+    /// SAFETY: You should check very careful since it is a ffi call, and it calls an unsafe function.
+    /// You should notice that, this is not marked as unsafe, but you should always remember, this is not a safe function.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn call_on<'c, R>(
+        self,
+        param: Param<'c, R>,
+        stream: &'c Stream<'_>,
+    ) -> Result<PendingResult<'c>, CUerror>
+    where
+        'b: 'c,
+    {
+        self.stream_call(param, stream.as_raw())
+    }
     /// Call a CUfunction, take care!
     /// SAFETY: You should check very careful since it is a ffi call, and it calls an unsafe function.
     /// You should notice that, this is not marked as unsafe, but you should always remember, this is not a safe function.
     #[must_use = "You should check whether the execution successes."]
     pub fn stream_call<'c, R>(
         self,
-        param: Param<'c, R>,
+        mut param: Param<'c, R>,
         stream: CUstream,
     ) -> Result<PendingResult<'c>, CUerror>
     where
         'b: 'c,
     {
+        let _ctx = self.device.bind()?;
         // SAFETY: Massive ffi calls.
         unsafe {
             let len = param.len;
@@ -336,29 +2045,68 @@ impl<'b> CUfunction<'b> {
                 // SAFETY in NonZero::new_unchecked: 1 != 0
                 Err(CUerror(NonZero::new_unchecked(1)))?
             }
-            let length = param.result.len() * mem::size_of::<R>();
-            let mut ret = ptr::null_mut();
-            cuMemAlloc(&mut ret, length)?;
-            cuMemcpyHtoDAsync(ret, param.result.as_ptr() as _, length, stream)?;
-            let mut device_mem = param
-                .input
-                .iter()
-                .map(|_| ptr::null_mut())
-                .collect::<Vec<_>>();
-            for (device, &(ptr, size)) in device_mem.iter_mut().zip(param.input.iter()) {
-                if size > 0 {
-                    cuMemAlloc(device, size)?;
-                    cuMemcpyHtoDAsync(*device, ptr, size, stream)?
+            if param.auto_block {
+                let (_, block) = self.max_potential_block_size(param.shared_mem, 0)?;
+                param.block_size = (block, 1, 1);
+                param.grid_size = (param.grid_len(block), 1, 1);
+            }
+            // built before the first copy is queued, thus every early return below syncs `stream` on drop
+            // before the temporaries and the host memory are released.
+            let mut pending = PendingResult {
+                device: self.device,
+                stream,
+                temporaries: Vec::with_capacity(param.input.len() + 1),
+                pending: true,
+            };
+            let alloc = |size| match param.temp_alloc {
+                TempAlloc::Cached => DeviceBuffer::uninit_cached(self.device, size),
+                TempAlloc::StreamOrdered => {
+                    DeviceBuffer::alloc_bytes_async(self.device, size, None, stream)
+                }
+                TempAlloc::Pool(pool) => {
+                    DeviceBuffer::alloc_bytes_async(self.device, size, Some(pool), stream)
+                }
+            };
+            let ret = match &param.result {
+                ParamResult::Host(result) => {
+                    let length = mem::size_of_val(*result);
+                    let ret = alloc(length)?;
+                    cuMemcpyHtoDAsync(ret.ptr, result.as_ptr() as _, length, stream)?;
+                    let ptr = ret.ptr;
+                    pending.temporaries.push(ret);
+                    ptr
+                }
+                &ParamResult::Device(ptr, ..) => ptr,
+            };
+            let mut device_mem = Vec::with_capacity(param.input.len() + 1);
+            // host inputs are read asynchronously, thus the launch must be synced before they are released.
+            let mut host_input = false;
+            for input in param.input.iter() {
+                match input {
+                    &ParamInput::Host(ptr, size) if size > 0 => {
+                        let buffer = alloc(size)?;
+                        cuMemcpyHtoDAsync(buffer.ptr, ptr, size, stream)?;
+                        host_input = true;
+                        device_mem.push(buffer.ptr);
+                        pending.temporaries.push(buffer);
+                    }
+                    ParamInput::Host(..) | ParamInput::Value(_) => device_mem.push(ptr::null_mut()),
+                    &ParamInput::Device(ptr) => device_mem.push(ptr),
                 }
             }
+            device_mem.push(ret);
+            // pointer arguments are sent as the address of the pointer, while values are sent as the address of themselves.
             let mut device_ref = device_mem
                 .iter_mut()
-                .chain(iter::once(&mut ret))
-                .map(|x| x as *mut _ as *mut c_void)
+                .zip(param.input.iter().map(Some).chain([None]))
+                .map(|(x, input)| match input {
+                    Some(ParamInput::Value(value)) => value.as_ptr(),
+                    _ => x as *mut _ as *mut c_void,
+                })
                 .collect::<Vec<_>>();
             // println!("{self:?} {:?}", device_mem);
             cuLaunchKernel(
-                self,
+                self.function,
                 param.grid_size.0,       // ----------
                 param.grid_size.1,       // grid 维度
                 param.grid_size.2,       // ----------
@@ -370,16 +2118,1583 @@ impl<'b> CUfunction<'b> {
                 device_ref.as_mut_ptr(), // 参数指针
                 ptr::null_mut(),
             )?;
-            cuMemcpyDtoHAsync(param.result.as_mut_ptr() as _, ret, length, stream)?
+            let host_result = if let ParamResult::Host(result) = param.result {
+                cuMemcpyDtoHAsync(
+                    result.as_mut_ptr() as _,
+                    ret,
+                    mem::size_of_val(result),
+                    stream,
+                )?;
+                true
+            } else {
+                false
+            };
+            if !matches!(param.temp_alloc, TempAlloc::Cached) {
+                // freed in stream order, after the launch and the copies finish.
+                pending.temporaries.clear();
+            }
+            pending.pending = host_result || host_input || !pending.temporaries.is_empty();
+            Ok(pending)
         }
-        Ok(PendingResult(PhantomData))
     }
 }
 
 impl<'c> PendingResult<'c> {
-    /// Wait for all the code finishes.
+    /// Wait for all the code finishes, then release the temporary device buffers.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn sync(mut self) -> CUresult {
+        let result = self
+            .device
+            .bind()
+            .and_then(|_ctx| unsafe { cuStreamSynchronize(self.stream) });
+        self.temporaries.clear();
+        self.pending = false;
+        result
+    }
+    /// Check whether all the work of its stream finishes, without blocking.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn query(&self) -> Result<bool, CUerror> {
+        let _ctx = self.device.bind()?;
+        query_stream(self.stream)
+    }
+    /// The stream this launch was sent to.
+    pub fn stream(&self) -> CUstream {
+        self.stream
+    }
+}
+fn query_stream(stream: CUstream) -> Result<bool, CUerror> {
+    match unsafe { cuStreamQuery(stream) } {
+        Ok(()) => Ok(true),
+        Err(e) if e.code() == CUerror::NOT_READY.code() => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+impl Drop for PendingResult<'_> {
+    fn drop(&mut self) {
+        if self.pending {
+            // The kernel might still use the temporaries or the result, they could only be released after it finishes.
+            let _ctx = self.device.bind();
+            let _ = unsafe { cuStreamSynchronize(self.stream) };
+        }
+    }
+}
+
+/// Owned device memory holding `len` elements of `T`, released with `cuMemFree` on drop.
+///
+/// Could be pushed into `Param` with `Param::push_device`, which passes its pointer to the kernel directly without copying.
+pub struct DeviceBuffer<'d, T> {
+    ptr: *mut c_void,
+    len: usize,
+    release: Release,
+    device: &'d Device,
+    _marker: PhantomData<T>,
+}
+unsafe impl<T: Send> Send for DeviceBuffer<'_, T> {}
+unsafe impl<T: Sync> Sync for DeviceBuffer<'_, T> {}
+/// How a `DeviceBuffer` releases its memory.
+#[derive(Copy, Clone, Debug)]
+enum Release {
+    Free,
+    /// stream-ordered allocations are freed in the stream they were allocated in.
+    FreeAsync(CUstream),
+    /// blocks from the caching allocator go back to it.
+    Cache,
+}
+impl<T> Drop for DeviceBuffer<'_, T> {
+    fn drop(&mut self) {
+        if !self.ptr.is_null() {
+            // errors (e.g., a previous kernel trapped) cannot be handled here, the memory is gone anyway.
+            let _ctx = self.device.bind();
+            let _ = unsafe {
+                match self.release {
+                    Release::Free => cuMemFree(self.ptr),
+                    Release::FreeAsync(stream) => cuMemFreeAsync(self.ptr, stream),
+                    Release::Cache => {
+                        self.device.cache_free(self.ptr, self.size());
+                        Ok(())
+                    }
+                }
+            };
+        }
+    }
+}
+impl<T> fmt::Debug for DeviceBuffer<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeviceBuffer")
+            .field("ptr", &self.ptr)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+impl<'d> DeviceBuffer<'d, u8> {
+    /// Allocate `size` bytes in stream order, from `pool` or the default pool of the device.
+    unsafe fn alloc_bytes_async(
+        device: &'d Device,
+        size: usize,
+        pool: Option<CUmemoryPool>,
+        stream: CUstream,
+    ) -> Result<Self, CUerror> {
+        let mut ptr = ptr::null_mut();
+        if size > 0 {
+            let _ctx = device.bind()?;
+            device.retry_on_oom(|| unsafe {
+                match pool {
+                    Some(pool) => cuMemAllocFromPoolAsync(&mut ptr, size, pool, stream),
+                    None => cuMemAllocAsync(&mut ptr, size, stream),
+                }
+            })?;
+        }
+        Ok(Self {
+            ptr,
+            len: size,
+            release: Release::FreeAsync(stream),
+            device,
+            _marker: PhantomData,
+        })
+    }
+}
+impl<'d, T> DeviceBuffer<'d, T> {
+    /// Allocate a buffer of `len` elements without initializing it.
+    ///
+    /// # Safety
+    /// Reading it before writing yields garbage, which is UB for types with invalid bit patterns.
+    pub unsafe fn uninit(device: &'d Device, len: usize) -> Result<Self, CUerror> {
+        let mut ptr = ptr::null_mut();
+        if len > 0 {
+            let _ctx = device.bind()?;
+            device.retry_on_oom(|| unsafe { cuMemAlloc(&mut ptr, len * mem::size_of::<T>()) })?;
+        }
+        Ok(Self {
+            ptr,
+            len,
+            release: Release::Free,
+            device,
+            _marker: PhantomData,
+        })
+    }
+    /// Allocate a buffer of `len` elements in stream order without initializing it, from `pool` or the default pool of the device.
+    /// The allocation could be used by work in `stream` (or work ordered after it) only, and is freed in `stream` on drop.
+    ///
+    /// # Safety
+    /// Reading it before writing yields garbage, which is UB for types with invalid bit patterns.
+    pub unsafe fn uninit_async(
+        stream: &'d Stream<'_>,
+        pool: Option<&MemPool<'_>>,
+        len: usize,
+    ) -> Result<Self, CUerror> {
+        let mut ptr = ptr::null_mut();
+        let size = len * mem::size_of::<T>();
+        if len > 0 {
+            let _ctx = stream.device.bind()?;
+            stream.device.retry_on_oom(|| unsafe {
+                match pool {
+                    Some(pool) => cuMemAllocFromPoolAsync(&mut ptr, size, pool.pool, stream.stream),
+                    None => cuMemAllocAsync(&mut ptr, size, stream.stream),
+                }
+            })?;
+        }
+        Ok(Self {
+            ptr,
+            len,
+            release: Release::FreeAsync(stream.stream),
+            device: stream.device,
+            _marker: PhantomData,
+        })
+    }
+    /// Allocate a buffer of `len` elements from the caching allocator of `device` without initializing it.
+    /// The block goes back to the cache on drop, thus it must not be dropped while a kernel still uses it.
+    ///
+    /// # Safety
+    /// Reading it before writing yields garbage, which is UB for types with invalid bit patterns.
+    pub unsafe fn uninit_cached(device: &'d Device, len: usize) -> Result<Self, CUerror> {
+        let size = len * mem::size_of::<T>();
+        let ptr = if len > 0 {
+            device.cache_alloc(size)?
+        } else {
+            ptr::null_mut()
+        };
+        Ok(Self {
+            ptr,
+            len,
+            release: Release::Cache,
+            device,
+            _marker: PhantomData,
+        })
+    }
+    /// Allocate a buffer of `len` elements with all bytes set to zero.
+    pub fn zeroed(device: &'d Device, len: usize) -> Result<Self, CUerror> {
+        unsafe {
+            let buffer = Self::uninit(device, len)?;
+            if !buffer.ptr.is_null() {
+                let _ctx = device.bind()?;
+                cuMemsetD8(buffer.ptr, 0, buffer.size())?;
+            }
+            Ok(buffer)
+        }
+    }
+    /// Allocate a buffer and upload `data` into it.
+    pub fn from_slice(device: &'d Device, data: &[T]) -> Result<Self, CUerror> {
+        unsafe {
+            let mut buffer = Self::uninit(device, data.len())?;
+            buffer.upload(data)?;
+            Ok(buffer)
+        }
+    }
+    /// Number of elements.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    /// Whether the buffer contains no element (and thus owns no device memory).
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// Size in bytes.
+    pub fn size(&self) -> usize {
+        self.len * mem::size_of::<T>()
+    }
+    /// Raw device pointer, null for an empty buffer.
+    pub fn as_raw(&self) -> *mut c_void {
+        self.ptr
+    }
+    /// Copy `src` from host into this buffer, `src` must have the same length.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn upload(&mut self, src: &[T]) -> CUresult {
+        if src.len() != self.len {
+            return Err(CUerror::INVALID_VALUE);
+        }
+        if self.ptr.is_null() {
+            return Ok(());
+        }
+        let _ctx = self.device.bind()?;
+        unsafe { cuMemcpyHtoD(self.ptr, src.as_ptr() as _, self.size()) }
+    }
+    /// Copy this buffer into host memory `dst`, `dst` must have the same length.
     #[must_use = "You should check whether the execution successes."]
-    pub fn sync(self) -> CUresult {
+    pub fn download(&self, dst: &mut [T]) -> CUresult {
+        if dst.len() != self.len {
+            return Err(CUerror::INVALID_VALUE);
+        }
+        if self.ptr.is_null() {
+            return Ok(());
+        }
+        let _ctx = self.device.bind()?;
+        unsafe { cuMemcpyDtoH(dst.as_mut_ptr() as _, self.ptr, self.size()) }
+    }
+    /// Download the whole buffer into a new `Vec`.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn to_vec(&self) -> Result<Vec<T>, CUerror>
+    where
+        T: Copy,
+    {
+        let mut vec = Vec::with_capacity(self.len);
+        if !self.ptr.is_null() {
+            let _ctx = self.device.bind()?;
+            unsafe {
+                cuMemcpyDtoH(vec.as_mut_ptr() as _, self.ptr, self.size())?;
+                vec.set_len(self.len);
+            }
+        }
+        Ok(vec)
+    }
+    /// Copy another device buffer with the same length into this buffer.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn copy_from(&mut self, src: &DeviceBuffer<'_, T>) -> CUresult {
+        src.copy_to(self)
+    }
+    /// Copy this buffer into another device buffer with the same length.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn copy_to(&self, dst: &mut DeviceBuffer<'_, T>) -> CUresult {
+        if dst.len != self.len {
+            return Err(CUerror::INVALID_VALUE);
+        }
+        if self.ptr.is_null() {
+            return Ok(());
+        }
+        let _ctx = self.device.bind()?;
+        unsafe { cuMemcpyDtoD(dst.ptr, self.ptr, self.size()) }
+    }
+    /// Copy this buffer into a buffer with the same length owned by any device, and wait until it finishes.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn copy_to_peer(&self, dst: &mut DeviceBuffer<'_, T>) -> CUresult {
+        self.copy_to_peer_raw(dst, Device::STREAM)?;
+        let _ctx = self.device.bind()?;
         unsafe { cuStreamSynchronize(Device::STREAM) }
     }
+    /// Copy this buffer into a buffer with the same length owned by any device, in stream order of `stream`,
+    /// which must belong to the device of this buffer.
+    ///
+    /// The copy uses `cuMemcpyPeerAsync` if the devices could access each other (see `Device::enable_peer_access`).
+    /// Otherwise it is staged through pinned host memory, which blocks: `stream` is synchronized, and the copy has finished
+    /// when this function returns.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn copy_to_peer_async(
+        &self,
+        dst: &mut DeviceBuffer<'_, T>,
+        stream: &Stream<'_>,
+    ) -> CUresult {
+        self.copy_to_peer_raw(dst, stream.stream)
+    }
+    fn copy_to_peer_raw(&self, dst: &mut DeviceBuffer<'_, T>, stream: CUstream) -> CUresult {
+        if dst.len != self.len {
+            return Err(CUerror::INVALID_VALUE);
+        }
+        if self.ptr.is_null() {
+            return Ok(());
+        }
+        let (src_device, dst_device) = (self.device, dst.device);
+        if src_device.context.0 == dst_device.context.0 {
+            let _ctx = src_device.bind()?;
+            return unsafe { cuMemcpyDtoDAsync(dst.ptr, self.ptr, self.size(), stream) };
+        }
+        if src_device.can_access_peer(dst_device)? && dst_device.can_access_peer(src_device)? {
+            let _ctx = src_device.bind()?;
+            return unsafe {
+                cuMemcpyPeerAsync(
+                    dst.ptr,
+                    dst_device.context,
+                    self.ptr,
+                    src_device.context,
+                    self.size(),
+                    stream,
+                )
+            };
+        }
+        // no P2P between them, stage through host memory.
+        // SAFETY: it is filled by the device-to-host copy before being read.
+        let mut staging = unsafe { PinnedBuffer::<u8>::uninit(src_device, self.size())? };
+        {
+            let _ctx = src_device.bind()?;
+            unsafe {
+                cuMemcpyDtoHAsync(staging.as_mut_ptr() as _, self.ptr, self.size(), stream)?;
+                cuStreamSynchronize(stream)?;
+            }
+        }
+        let _ctx = dst_device.bind()?;
+        unsafe { cuMemcpyHtoD(dst.ptr, staging.as_ptr() as _, self.size()) }
+    }
+}
+
+/// Page-locked host memory allocated by `cuMemAllocHost`, released with `cuMemFreeHost` on drop.
+///
+/// It derefs to a slice, thus could be used wherever `Param` accepts host data (e.g., `Param::new(&mut pinned)`),
+/// and the copies of `Function::stream_call` become truly asynchronous.
+/// ```no_run
+/// # use cuda_min::{Device, Param, PinnedBuffer, Stream};
+/// # let device = Device::init();
+/// # let module = device.compile("").unwrap();
+/// # let func = module.get_function("kernel").unwrap();
+/// let stream = Stream::new(&device).unwrap();
+/// let input = PinnedBuffer::from_slice(&device, &[1u32; 1024]).unwrap();
+/// let mut output = PinnedBuffer::<u32>::zeroed(&device, 1024).unwrap();
+/// let pending = func.call_on(Param::new(&mut output).push(&input), &stream).unwrap();
+/// // the host is free to do other work here.
+/// pending.sync().unwrap();
+/// ```
+pub struct PinnedBuffer<'d, T> {
+    ptr: *mut T,
+    len: usize,
+    device: &'d Device,
+    _marker: PhantomData<T>,
+}
+unsafe impl<T: Send> Send for PinnedBuffer<'_, T> {}
+unsafe impl<T: Sync> Sync for PinnedBuffer<'_, T> {}
+impl<T> Drop for PinnedBuffer<'_, T> {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            let _ctx = self.device.bind();
+            let _ = unsafe { cuMemFreeHost(self.ptr as _) };
+        }
+    }
+}
+impl<T: fmt::Debug> fmt::Debug for PinnedBuffer<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PinnedBuffer").field(&self.deref()).finish()
+    }
+}
+impl<T> Deref for PinnedBuffer<'_, T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+impl<T> DerefMut for PinnedBuffer<'_, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+impl<'d, T> PinnedBuffer<'d, T> {
+    /// Allocate `len` elements without initializing them.
+    ///
+    /// # Safety
+    /// Reading it before writing yields garbage, which is UB for types with invalid bit patterns.
+    pub unsafe fn uninit(device: &'d Device, len: usize) -> Result<Self, CUerror> {
+        let mut ptr = ptr::NonNull::<T>::dangling().as_ptr();
+        if len > 0 {
+            let _ctx = device.bind()?;
+            let mut raw = ptr::null_mut();
+            unsafe { cuMemAllocHost(&mut raw, len * mem::size_of::<T>())? }
+            ptr = raw as _;
+        }
+        Ok(Self {
+            ptr,
+            len,
+            device,
+            _marker: PhantomData,
+        })
+    }
+    /// Allocate `len` elements with all bytes set to zero.
+    pub fn zeroed(device: &'d Device, len: usize) -> Result<Self, CUerror> {
+        // SAFETY: all the bytes are written before returning.
+        let buffer = unsafe { Self::uninit(device, len)? };
+        unsafe { ptr::write_bytes(buffer.ptr as *mut u8, 0, len * mem::size_of::<T>()) }
+        Ok(buffer)
+    }
+    /// Allocate and copy `data` into it.
+    pub fn from_slice(device: &'d Device, data: &[T]) -> Result<Self, CUerror>
+    where
+        T: Copy,
+    {
+        let mut buffer = Self::zeroed(device, data.len())?;
+        buffer.copy_from_slice(data);
+        Ok(buffer)
+    }
+}
+
+/// A host slice registered as page-locked memory by `cuMemHostRegister`, unregistered on drop.
+///
+/// Pins existing memory (e.g., a large `Vec`) without copying it into a `PinnedBuffer`.
+/// Registering is expensive, it is worth only for memory that is transferred many times.
+pub struct PinnedSlice<'a, T> {
+    slice: &'a mut [T],
+    device: &'a Device,
+}
+impl<T> Drop for PinnedSlice<'_, T> {
+    fn drop(&mut self) {
+        if !self.slice.is_empty() {
+            let _ctx = self.device.bind();
+            let _ = unsafe { cuMemHostUnregister(self.slice.as_mut_ptr() as _) };
+        }
+    }
+}
+impl<T: fmt::Debug> fmt::Debug for PinnedSlice<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PinnedSlice").field(&self.slice).finish()
+    }
+}
+impl<T> Deref for PinnedSlice<'_, T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        self.slice
+    }
+}
+impl<T> DerefMut for PinnedSlice<'_, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.slice
+    }
+}
+impl<'a, T> PinnedSlice<'a, T> {
+    /// Page-lock `slice` until the returned value is dropped.
+    pub fn register(device: &'a Device, slice: &'a mut [T]) -> Result<Self, CUerror> {
+        if !slice.is_empty() {
+            let _ctx = device.bind()?;
+            unsafe { cuMemHostRegister(slice.as_mut_ptr() as _, mem::size_of_val(slice), 0)? }
+        }
+        Ok(Self { slice, device })
+    }
+}
+
+/// Where unified memory should be moved to or prefer to stay.
+#[derive(Copy, Clone, Debug)]
+pub enum MemLocation<'a> {
+    Device(&'a Device),
+    Host,
+}
+impl MemLocation<'_> {
+    fn as_raw(&self) -> CUdevice {
+        match self {
+            Self::Device(device) => device.device,
+            // CU_DEVICE_CPU
+            Self::Host => CUdevice(-1),
+        }
+    }
+}
+/// Hints about the usage of unified memory, see `cuMemAdvise`.
+#[repr(i32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MemAdvice {
+    /// Mostly read, the driver may create read-only copies on every processor accessing it.
+    SetReadMostly = 1,
+    UnsetReadMostly = 2,
+    /// Prefer to keep the memory at the given location.
+    SetPreferredLocation = 3,
+    UnsetPreferredLocation = 4,
+    /// The memory is accessed by the given device, mapping it there to avoid page faults.
+    SetAccessedBy = 5,
+    UnsetAccessedBy = 6,
+}
+/// Unified memory allocated by `cuMemAllocManaged`, accessible from both host and kernels, released with `cuMemFree` on drop.
+///
+/// It derefs to a slice on the host, and could be sent to kernels with `Param::push_managed` or `Param::new_managed` without any copy.
+/// Unless the GPU supports concurrent managed access, the host must not touch it while a kernel using it is running.
+pub struct ManagedBuffer<'d, T> {
+    ptr: *mut T,
+    len: usize,
+    device: &'d Device,
+    _marker: PhantomData<T>,
+}
+unsafe impl<T: Send> Send for ManagedBuffer<'_, T> {}
+unsafe impl<T: Sync> Sync for ManagedBuffer<'_, T> {}
+impl<T> Drop for ManagedBuffer<'_, T> {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            let _ctx = self.device.bind();
+            let _ = unsafe { cuMemFree(self.ptr as _) };
+        }
+    }
+}
+impl<T: fmt::Debug> fmt::Debug for ManagedBuffer<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ManagedBuffer").field(&self.deref()).finish()
+    }
+}
+impl<T> Deref for ManagedBuffer<'_, T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+impl<T> DerefMut for ManagedBuffer<'_, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+impl<'d, T> ManagedBuffer<'d, T> {
+    /// Allocate `len` elements with all bytes set to zero.
+    pub fn zeroed(device: &'d Device, len: usize) -> Result<Self, CUerror> {
+        let size = len * mem::size_of::<T>();
+        let mut ptr = ptr::NonNull::<T>::dangling().as_ptr();
+        if len > 0 {
+            let _ctx = device.bind()?;
+            let mut raw = ptr::null_mut();
+            // CU_MEM_ATTACH_GLOBAL = 1
+            device.retry_on_oom(|| unsafe { cuMemAllocManaged(&mut raw, size, 1) })?;
+            unsafe { ptr::write_bytes(raw as *mut u8, 0, size) }
+            ptr = raw as _;
+        }
+        Ok(Self {
+            ptr,
+            len,
+            device,
+            _marker: PhantomData,
+        })
+    }
+    /// Allocate and copy `data` into it.
+    pub fn from_slice(device: &'d Device, data: &[T]) -> Result<Self, CUerror>
+    where
+        T: Copy,
+    {
+        let mut buffer = Self::zeroed(device, data.len())?;
+        buffer.copy_from_slice(data);
+        Ok(buffer)
+    }
+    /// Unified pointer, valid on both host and device.
+    pub fn as_raw(&self) -> *mut c_void {
+        self.ptr as _
+    }
+    /// Migrate the memory to `location` in stream order, to avoid page faults on first touch.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn prefetch(&self, location: MemLocation<'_>, stream: &Stream<'_>) -> CUresult {
+        self.prefetch_raw(location, stream.as_raw())
+    }
+    /// Migrate the memory to `location` in the null stream.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn prefetch_default(&self, location: MemLocation<'_>) -> CUresult {
+        self.prefetch_raw(location, Device::STREAM)
+    }
+    fn prefetch_raw(&self, location: MemLocation<'_>, stream: CUstream) -> CUresult {
+        if self.len == 0 {
+            return Ok(());
+        }
+        let _ctx = self.device.bind()?;
+        unsafe { cuMemPrefetchAsync(self.ptr as _, self.size(), location.as_raw(), stream) }
+    }
+    /// Give the driver a hint about how the memory is used. `location` is ignored by read-mostly advices.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn advise(&self, advice: MemAdvice, location: MemLocation<'_>) -> CUresult {
+        if self.len == 0 {
+            return Ok(());
+        }
+        let _ctx = self.device.bind()?;
+        unsafe {
+            cuMemAdvise(
+                self.ptr as _,
+                self.size(),
+                advice as c_int,
+                location.as_raw(),
+            )
+        }
+    }
+    /// Size in bytes.
+    pub fn size(&self) -> usize {
+        self.len * mem::size_of::<T>()
+    }
+}
+
+/// Memory pool for stream-ordered allocations, see `DeviceBuffer::uninit_async` and `Param::pool`.
+/// A pool created by `MemPool::new` is destroyed on drop, while the default pool of a device is never destroyed.
+#[derive(Debug)]
+pub struct MemPool<'d> {
+    pool: CUmemoryPool,
+    owned: bool,
+    device: &'d Device,
+}
+impl Drop for MemPool<'_> {
+    fn drop(&mut self) {
+        if self.owned {
+            // outstanding allocations keep the pool alive until they are freed.
+            let _ctx = self.device.bind();
+            let _ = unsafe { cuMemPoolDestroy(self.pool) };
+        }
+    }
+}
+impl<'d> MemPool<'d> {
+    /// The default pool of `device`, which `cuMemAllocAsync` uses.
+    pub fn default_pool(device: &'d Device) -> Result<Self, CUerror> {
+        let mut pool = CUmemoryPool(ptr::null_mut());
+        unsafe { cuDeviceGetDefaultMemPool(&mut pool, device.device)? }
+        Ok(Self {
+            pool,
+            owned: false,
+            device,
+        })
+    }
+    /// Create a new pool of pinned device memory on `device`.
+    pub fn new(device: &'d Device) -> Result<Self, CUerror> {
+        // CU_MEM_ALLOCATION_TYPE_PINNED = 1, CU_MEM_LOCATION_TYPE_DEVICE = 1
+        let props = CUmemPoolProps {
+            alloc_type: 1,
+            handle_types: 0,
+            location_type: 1,
+            location_id: device.device.0,
+            win32_security_attributes: ptr::null_mut(),
+            max_size: 0,
+            usage: 0,
+            reserved: [0; 54],
+        };
+        let mut pool = CUmemoryPool(ptr::null_mut());
+        let _ctx = device.bind()?;
+        unsafe { cuMemPoolCreate(&mut pool, &props)? }
+        Ok(Self {
+            pool,
+            owned: true,
+            device,
+        })
+    }
+    /// The raw pool handle.
+    pub fn as_raw(&self) -> CUmemoryPool {
+        self.pool
+    }
+    fn get_u64(&self, attr: c_int) -> Result<u64, CUerror> {
+        let mut value = 0u64;
+        let _ctx = self.device.bind()?;
+        unsafe { cuMemPoolGetAttribute(self.pool, attr, &mut value as *mut u64 as _)? }
+        Ok(value)
+    }
+    /// Bytes of reserved memory the pool keeps when synchronizing, rather than releasing them to the OS.
+    /// The driver default is 0, which releases everything at each synchronization; `u64::MAX` keeps all of them.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn set_release_threshold(&self, bytes: u64) -> CUresult {
+        let mut value = bytes;
+        // CU_MEMPOOL_ATTR_RELEASE_THRESHOLD = 4
+        let _ctx = self.device.bind()?;
+        unsafe { cuMemPoolSetAttribute(self.pool, 4, &mut value as *mut u64 as _) }
+    }
+    /// Current release threshold in bytes.
+    pub fn release_threshold(&self) -> Result<u64, CUerror> {
+        self.get_u64(4)
+    }
+    /// Bytes of memory currently reserved by the pool (CU_MEMPOOL_ATTR_RESERVED_MEM_CURRENT).
+    pub fn reserved_mem_current(&self) -> Result<u64, CUerror> {
+        self.get_u64(5)
+    }
+    /// Bytes of memory currently allocated from the pool (CU_MEMPOOL_ATTR_USED_MEM_CURRENT).
+    pub fn used_mem_current(&self) -> Result<u64, CUerror> {
+        self.get_u64(7)
+    }
+    /// Release memory back to the OS until the pool keeps at most `min_bytes_to_keep` unused bytes.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn trim_to(&self, min_bytes_to_keep: usize) -> CUresult {
+        let _ctx = self.device.bind()?;
+        unsafe { cuMemPoolTrimTo(self.pool, min_bytes_to_keep) }
+    }
+}
+
+/// Stream creation flags.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StreamFlags {
+    /// CU_STREAM_DEFAULT, implicitly synchronizes with the null stream.
+    Default = 0,
+    /// CU_STREAM_NON_BLOCKING, work in it may run concurrently with the null stream.
+    NonBlocking = 1,
+}
+/// Owned CUDA stream, destroyed with `cuStreamDestroy` on drop.
+#[derive(Debug)]
+pub struct Stream<'d> {
+    stream: CUstream,
+    device: &'d Device,
+}
+impl Drop for Stream<'_> {
+    fn drop(&mut self) {
+        // pending work still completes, the driver releases the stream afterwards.
+        let _ctx = self.device.bind();
+        let _ = unsafe { cuStreamDestroy(self.stream) };
+    }
+}
+impl<'d> Stream<'d> {
+    /// Create a stream with default flags and priority.
+    pub fn new(device: &'d Device) -> Result<Self, CUerror> {
+        Self::with_flags(device, StreamFlags::Default)
+    }
+    /// Create a stream with the given flags.
+    pub fn with_flags(device: &'d Device, flags: StreamFlags) -> Result<Self, CUerror> {
+        let mut stream = CUstream(ptr::null_mut());
+        let _ctx = device.bind()?;
+        unsafe { cuStreamCreate(&mut stream, flags as u32)? }
+        Ok(Self { stream, device })
+    }
+    /// Create a stream with the given flags and priority, lower numbers represent higher priorities.
+    /// The priority is clamped by the driver into `Device::stream_priority_range`.
+    pub fn with_priority(
+        device: &'d Device,
+        flags: StreamFlags,
+        priority: c_int,
+    ) -> Result<Self, CUerror> {
+        let mut stream = CUstream(ptr::null_mut());
+        let _ctx = device.bind()?;
+        unsafe { cuStreamCreateWithPriority(&mut stream, flags as u32, priority)? }
+        Ok(Self { stream, device })
+    }
+    /// The raw stream handle.
+    pub fn as_raw(&self) -> CUstream {
+        self.stream
+    }
+    /// Wait until all the work in this stream finishes.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn sync(&self) -> CUresult {
+        let _ctx = self.device.bind()?;
+        unsafe { cuStreamSynchronize(self.stream) }
+    }
+    /// Check whether all the work in this stream finishes, without blocking.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn query(&self) -> Result<bool, CUerror> {
+        let _ctx = self.device.bind()?;
+        query_stream(self.stream)
+    }
+    /// Priority of this stream.
+    pub fn priority(&self) -> Result<c_int, CUerror> {
+        let mut priority = 0;
+        let _ctx = self.device.bind()?;
+        unsafe { cuStreamGetPriority(self.stream, &mut priority)? }
+        Ok(priority)
+    }
+    /// Make all future work in this stream wait until `event` completes, without blocking the host.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn wait_event(&self, event: &Event<'_>) -> CUresult {
+        let _ctx = self.device.bind()?;
+        unsafe { cuStreamWaitEvent(self.stream, event.event, 0) }
+    }
+    /// Start recording the work sent to this stream into a graph rather than running it, see `Graph`.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn begin_capture(&self, mode: CaptureMode) -> CUresult {
+        let _ctx = self.device.bind()?;
+        unsafe { cuStreamBeginCapture(self.stream, mode as c_int) }
+    }
+    /// Stop recording and return the recorded graph.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn end_capture(&self) -> Result<Graph<'d>, CUerror> {
+        let mut graph = CUgraph(ptr::null_mut());
+        let _ctx = self.device.bind()?;
+        unsafe { cuStreamEndCapture(self.stream, &mut graph)? }
+        Ok(Graph {
+            graph,
+            device: self.device,
+            _marker: PhantomData,
+        })
+    }
+    /// Record the work `f` sends to this stream into a graph, the capture is ended even if `f` fails.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn capture(
+        &self,
+        mode: CaptureMode,
+        f: impl FnOnce(&Self) -> CUresult,
+    ) -> Result<Graph<'d>, CUerror> {
+        self.begin_capture(mode)?;
+        let result = f(self);
+        let graph = self.end_capture();
+        result?;
+        graph
+    }
+    /// Flags of this stream.
+    pub fn flags(&self) -> Result<StreamFlags, CUerror> {
+        let mut flags = 0;
+        let _ctx = self.device.bind()?;
+        unsafe { cuStreamGetFlags(self.stream, &mut flags)? }
+        Ok(if flags & 1 == 1 {
+            StreamFlags::NonBlocking
+        } else {
+            StreamFlags::Default
+        })
+    }
+}
+
+/// Event creation flags, could be combined with `|`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct EventFlags(pub c_uint);
+impl EventFlags {
+    /// CU_EVENT_DEFAULT, timing enabled and `sync` spins.
+    pub const DEFAULT: Self = Self(0);
+    /// CU_EVENT_BLOCKING_SYNC, `sync` blocks the host thread rather than spinning.
+    pub const BLOCKING_SYNC: Self = Self(1);
+    /// CU_EVENT_DISABLE_TIMING, cheaper events which could only be used for ordering.
+    pub const DISABLE_TIMING: Self = Self(2);
+}
+impl BitOr for EventFlags {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+/// Owned CUDA event, destroyed with `cuEventDestroy` on drop.
+///
+/// Measures GPU time between two points of a stream, or orders work between streams with `Stream::wait_event`:
+/// ```no_run
+/// # use cuda_min::{Device, Event, Param};
+/// # let device = Device::init();
+/// # let module = device.compile("").unwrap();
+/// # let func = module.get_function("kernel").unwrap();
+/// # let mut ret = [0u32; 1024];
+/// let start = Event::new(&device).unwrap();
+/// let end = Event::new(&device).unwrap();
+/// start.record_default().unwrap();
+/// let res = func.call(Param::new(&mut ret)).unwrap();
+/// end.record_default().unwrap();
+/// res.sync().unwrap();
+/// println!("kernel and copies cost {:?}", end.elapsed_since(&start).unwrap());
+/// ```
+#[derive(Debug)]
+pub struct Event<'d> {
+    event: CUevent,
+    device: &'d Device,
+}
+impl Drop for Event<'_> {
+    fn drop(&mut self) {
+        let _ctx = self.device.bind();
+        let _ = unsafe { cuEventDestroy(self.event) };
+    }
+}
+impl<'d> Event<'d> {
+    /// Create an event with timing enabled.
+    pub fn new(device: &'d Device) -> Result<Self, CUerror> {
+        Self::with_flags(device, EventFlags::DEFAULT)
+    }
+    /// Create an event with the given flags.
+    pub fn with_flags(device: &'d Device, flags: EventFlags) -> Result<Self, CUerror> {
+        let mut event = CUevent(ptr::null_mut());
+        let _ctx = device.bind()?;
+        unsafe { cuEventCreate(&mut event, flags.0)? }
+        Ok(Self { event, device })
+    }
+    /// The raw event handle.
+    pub fn as_raw(&self) -> CUevent {
+        self.event
+    }
+    /// Record this event at the current position of `stream`.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn record(&self, stream: &Stream<'_>) -> CUresult {
+        let _ctx = self.device.bind()?;
+        unsafe { cuEventRecord(self.event, stream.stream) }
+    }
+    /// Record this event at the current position of the null stream, which `Function::call` uses.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn record_default(&self) -> CUresult {
+        let _ctx = self.device.bind()?;
+        unsafe { cuEventRecord(self.event, Device::STREAM) }
+    }
+    /// Wait until the recorded work completes.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn sync(&self) -> CUresult {
+        let _ctx = self.device.bind()?;
+        unsafe { cuEventSynchronize(self.event) }
+    }
+    /// Check whether the recorded work completes, without blocking.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn query(&self) -> Result<bool, CUerror> {
+        let _ctx = self.device.bind()?;
+        match unsafe { cuEventQuery(self.event) } {
+            Ok(()) => Ok(true),
+            Err(e) if e.code() == CUerror::NOT_READY.code() => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+    /// GPU time elapsed from `start` to this event, both must be recorded with timing enabled and completed.
+    /// The resolution is around 0.5 microseconds, CUDA_ERROR_INVALID_VALUE is returned if `start` was recorded after this event.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn elapsed_since(&self, start: &Event<'_>) -> Result<Duration, CUerror> {
+        let mut milliseconds = 0.0;
+        let _ctx = self.device.bind()?;
+        unsafe { cuEventElapsedTime(&mut milliseconds, start.event, self.event)? }
+        // negative if the events are passed in the wrong order.
+        Duration::try_from_secs_f32(milliseconds / 1000.0).map_err(|_| CUerror::INVALID_VALUE)
+    }
+}
+
+/// Result of `Linker::complete`, a cubin image with the logs of linking.
+#[derive(Clone, Debug)]
+pub struct LinkedImage {
+    pub image: Vec<u8>,
+    pub info_log: String,
+    pub wall_time: Duration,
+}
+/// Runtime linker, combines several PTX/cubin/fatbin inputs (e.g., one PTX per GPU crate) into one cubin image,
+/// so that `.extern` device functions declared in one input could be defined in another.
+/// ```no_run
+/// # use cuda_min::{CompileOptions, Device, Linker};
+/// # let device = Device::init();
+/// let kernels = std::fs::read_to_string("kernels.ptx").unwrap(); // declares `.extern .func helper`
+/// let helpers = std::fs::read_to_string("helpers.ptx").unwrap(); // defines `.visible .func helper`
+/// let mut linker = Linker::new(&device, &CompileOptions::new()).unwrap();
+/// linker.add_ptx(&kernels, "kernels").unwrap();
+/// linker.add_ptx(&helpers, "helpers").unwrap();
+/// let module = linker.load().unwrap();
+/// let func = module.get_function("kernel").unwrap();
+/// ```
+pub struct Linker<'d> {
+    device: &'d Device,
+    state: CUlinkState,
+    // the driver keeps writing into the log buffers and option values until the state is destroyed.
+    logs: JitLogs,
+    #[allow(dead_code)]
+    options: Vec<c_int>,
+    values: Vec<*mut c_void>,
+    wall_time: usize,
+}
+impl Drop for Linker<'_> {
+    fn drop(&mut self) {
+        let _ctx = self.device.bind();
+        let _ = unsafe { cuLinkDestroy(self.state) };
+    }
+}
+impl<'d> Linker<'d> {
+    /// Create a linker, `compile_options` apply to every PTX input.
+    pub fn new(device: &'d Device, compile_options: &CompileOptions) -> Result<Self, CUerror> {
+        let mut logs = JitLogs::new();
+        let mut options = Vec::new();
+        let mut values = Vec::new();
+        logs.push_options(&mut options, &mut values);
+        compile_options.push_options(&mut options, &mut values);
+        let wall_time = push_wall_time_option(&mut options, &mut values);
+        let mut state = CUlinkState(ptr::null_mut());
+        let _ctx = device.bind()?;
+        unsafe {
+            cuLinkCreate(
+                options.len() as c_uint,
+                options.as_mut_ptr(),
+                values.as_mut_ptr(),
+                &mut state,
+            )?
+        }
+        Ok(Self {
+            device,
+            state,
+            logs,
+            options,
+            values,
+            wall_time,
+        })
+    }
+    /// Add PTX code, `name` is only used in logs.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn add_ptx(&mut self, ptx: &str, name: &str) -> Result<(), CompileError> {
+        let Ok(cstr) = CString::new(ptx) else {
+            return Err(CompileError {
+                error: CUerror(NonZero::new(218).unwrap()),
+                log: format!("{name}: PTX code should not contain any nul byte"),
+            });
+        };
+        // According to https://docs.nvidia.com/cuda/cuda-driver-api/group__CUDA__TYPES.html
+        // CU_JIT_INPUT_PTX = 1
+        self.add_data(1, cstr.as_bytes_with_nul(), name)
+    }
+    /// Add an image, whose kind (PTX, cubin or fatbin) is detected by its header, `name` is only used in logs.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn add_image(&mut self, image: &[u8], name: &str) -> Result<(), CompileError> {
+        if ImageKind::is_fatbin_wrapper(image) {
+            // CUDA_ERROR_INVALID_IMAGE = 200
+            return Err(CompileError {
+                error: CUerror(NonZero::new(200).unwrap()),
+                log: format!("{name}: image is a fatbin wrapper rather than a fatbin"),
+            });
+        }
+        // CU_JIT_INPUT_CUBIN = 0, CU_JIT_INPUT_FATBINARY = 2
+        match ImageKind::detect(image) {
+            ImageKind::Ptx => match str::from_utf8(image.strip_suffix(&[0]).unwrap_or(image)) {
+                Ok(ptx) => self.add_ptx(ptx, name),
+                Err(_) => Err(CompileError {
+                    error: CUerror(NonZero::new(218).unwrap()),
+                    log: format!("{name}: image is neither cubin nor fatbin, nor valid PTX text"),
+                }),
+            },
+            ImageKind::Cubin => self.add_data(0, image, name),
+            ImageKind::Fatbin => self.add_data(2, image, name),
+        }
+    }
+    fn add_data(&mut self, input_type: c_int, data: &[u8], name: &str) -> Result<(), CompileError> {
+        let name = CString::new(name).unwrap_or_default();
+        let _ctx = self.device.bind().map_err(|error| CompileError {
+            error,
+            log: String::new(),
+        })?;
+        unsafe {
+            cuLinkAddData(
+                self.state,
+                input_type,
+                data.as_ptr() as _,
+                data.len(),
+                name.as_ptr(),
+                0,
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        }
+        .map_err(|error| CompileError {
+            error,
+            log: self.logs.error(),
+        })
+    }
+    /// Link all the inputs into a cubin image.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn complete(self) -> Result<LinkedImage, CompileError> {
+        let mut cubin = ptr::null_mut();
+        let mut size = 0;
+        let _ctx = self.device.bind().map_err(|error| CompileError {
+            error,
+            log: String::new(),
+        })?;
+        unsafe { cuLinkComplete(self.state, &mut cubin, &mut size) }.map_err(|error| {
+            CompileError {
+                error,
+                log: self.logs.error(),
+            }
+        })?;
+        // the image is owned by the link state, copy it before the state is destroyed.
+        let image = unsafe { std::slice::from_raw_parts(cubin as *const u8, size) }.to_vec();
+        Ok(LinkedImage {
+            image,
+            info_log: self.logs.info(),
+            wall_time: read_wall_time(&self.values, self.wall_time),
+        })
+    }
+    /// Link all the inputs and load the result as a module.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn load(self) -> Result<Module<'d>, CompileError> {
+        let device = self.device;
+        let linked = self.complete()?;
+        let mut module = device.load_image(&linked.image)?;
+        module.info_log = linked.info_log;
+        module.wall_time = linked.wall_time;
+        Ok(module)
+    }
+}
+
+/// How `MultiDevice` splits the result among its devices.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Split {
+    /// Every device gets the same number of elements.
+    Even,
+    /// Devices get elements proportional to their multiprocessor counts.
+    BySmCount,
+}
+/// Several GPUs running one logical launch together, see `MultiModule::call`.
+/// ```no_run
+/// # use cuda_min::{MultiDevice, Param, Split};
+/// # let ptx = "";
+/// let gpus = MultiDevice::init_all().unwrap().split(Split::BySmCount);
+/// let module = gpus.compile(ptx).unwrap();
+/// let input = vec![1u32; 1 << 20];
+/// let mut ret = vec![0u32; 1 << 20];
+/// // the kernel is `fn kernel(input: *const u32, chunk: Chunk, ret: *mut u32)`
+/// module.call("kernel", Param::new(&mut ret).block_size(256).push(&input)).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct MultiDevice {
+    devices: Vec<Device>,
+    split: Split,
+}
+impl MultiDevice {
+    /// Use `devices`, splitting evenly by default.
+    pub fn new(devices: Vec<Device>) -> Self {
+        Self {
+            devices,
+            split: Split::Even,
+        }
+    }
+    /// Use all the GPUs, splitting evenly by default.
+    pub fn init_all() -> Result<Self, CUerror> {
+        Ok(Self::new(Device::init_all()?))
+    }
+    /// Set how the result is split.
+    pub fn split(mut self, split: Split) -> Self {
+        self.split = split;
+        self
+    }
+    /// The devices used.
+    pub fn devices(&self) -> &[Device] {
+        &self.devices
+    }
+    /// Enable peer access between every pair of devices supporting it, see `Device::enable_peer_access`.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn enable_peer_access(&self) -> CUresult {
+        for device in &self.devices {
+            for peer in &self.devices {
+                if device.can_access_peer(peer)? {
+                    device.enable_peer_access(peer)?;
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Compile `ptx` on every device.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn compile(&self, ptx: &str) -> Result<MultiModule<'_>, CompileError> {
+        self.load_with(|device| device.compile(ptx))
+    }
+    /// Load an image (PTX, cubin or fatbin) on every device, see `Device::load_image`.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn load_image(&self, image: &[u8]) -> Result<MultiModule<'_>, CompileError> {
+        self.load_with(|device| device.load_image(image))
+    }
+    fn load_with<'d>(
+        &'d self,
+        load: impl Fn(&'d Device) -> Result<Module<'d>, CompileError>,
+    ) -> Result<MultiModule<'d>, CompileError> {
+        let mut modules = Vec::with_capacity(self.devices.len());
+        let mut weights = Vec::with_capacity(self.devices.len());
+        for device in &self.devices {
+            modules.push(load(device)?);
+            weights.push(match self.split {
+                Split::Even => 1,
+                Split::BySmCount => device
+                    .get_attribute(DeviceAttribute::MultiprocessorCount)
+                    .map_err(|error| CompileError {
+                        error,
+                        log: String::new(),
+                    })? as u64,
+            });
+        }
+        Ok(MultiModule { modules, weights })
+    }
+}
+/// A module loaded on every device of a `MultiDevice`.
+#[derive(Debug)]
+pub struct MultiModule<'d> {
+    modules: Vec<Module<'d>>,
+    weights: Vec<u64>,
+}
+/// Moves the raw pointers of a chunk launch into its thread.
+struct AssertSend<T>(T);
+// The host inputs are only read, and the result chunks are disjoint, all of them outlive the scope of the threads.
+unsafe impl<T> Send for AssertSend<T> {}
+impl MultiModule<'_> {
+    /// The modules, one per device.
+    pub fn modules(&self) -> &[Module<'_>] {
+        &self.modules
+    }
+    /// Ranges of the result handled by each device, for a result of `len` elements.
+    pub fn ranges(&self, len: usize) -> Vec<std::ops::Range<usize>> {
+        let total = self.weights.iter().sum::<u64>().max(1) as u128;
+        let mut acc = 0;
+        let mut start = 0;
+        self.weights
+            .iter()
+            .map(|&weight| {
+                acc += weight as u128;
+                let end = (len as u128 * acc / total) as usize;
+                let range = start..end;
+                start = end;
+                range
+            })
+            .collect()
+    }
+    /// Split `param` among the devices, run the chunks concurrently (one host thread per device),
+    /// and write them back into the host result of `param`.
+    ///
+    /// Host inputs and values are sent to every device unchanged, the `Chunk` of each device is appended as the last input.
+    /// Returns CUDA_ERROR_INVALID_VALUE for device inputs or results, memory pools and 2d/3d blocks, which belong to one device only.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn call<R: Send>(&self, name: &str, param: Param<'_, R>) -> CUresult {
+        let Param {
+            input,
+            result: ParamResult::Host(result),
+            shared_mem,
+            block_size: (block_size, 1, 1),
+            auto_block,
+            round_up,
+            temp_alloc: temp_alloc @ (TempAlloc::Cached | TempAlloc::StreamOrdered),
+            ..
+        } = param
+        else {
+            return Err(CUerror::INVALID_VALUE);
+        };
+        if input.iter().any(|x| matches!(x, ParamInput::Device(_))) {
+            return Err(CUerror::INVALID_VALUE);
+        }
+        let mut chunks = Vec::with_capacity(self.modules.len());
+        let mut rest = result;
+        let mut offset = 0;
+        for (module, range) in self.modules.iter().zip(self.ranges(rest.len())) {
+            let (chunk, tail) = mem::take(&mut rest).split_at_mut(range.len());
+            rest = tail;
+            if chunk.is_empty() {
+                continue;
+            }
+            let len = chunk.len();
+            let mut chunk_param = Param::new(chunk).shared(shared_mem);
+            chunk_param = if auto_block {
+                chunk_param.auto_block_size()
+            } else {
+                chunk_param.block_size(block_size)
+            };
+            if round_up {
+                chunk_param = chunk_param.round_up();
+            }
+            if matches!(temp_alloc, TempAlloc::StreamOrdered) {
+                chunk_param = chunk_param.stream_ordered();
+            }
+            chunk_param.input = input
+                .iter()
+                .map(|x| match x {
+                    &ParamInput::Host(ptr, size) => ParamInput::Host(ptr, size),
+                    ParamInput::Value(value) => ParamInput::Value(value.clone()),
+                    &ParamInput::Device(ptr) => ParamInput::Device(ptr),
+                })
+                .collect();
+            chunk_param = chunk_param.push_value(crate::Chunk {
+                offset: offset as u64,
+                len: len as u64,
+            });
+            offset += len;
+            chunks.push((module, AssertSend(chunk_param)));
+        }
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = chunks
+                .into_iter()
+                .map(|(module, chunk_param)| {
+                    scope.spawn(move || {
+                        // capture the whole wrapper rather than its (non-`Send`) field.
+                        let chunk_param = chunk_param;
+                        module.get_function(name)?.call(chunk_param.0)?.sync()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|e| std::panic::resume_unwind(e))
+                })
+                .fold(Ok(()), CUresult::and)
+        })
+    }
+}
+
+/// Which API calls of other threads could break a stream capture, see `cuStreamBeginCapture`.
+#[repr(i32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CaptureMode {
+    /// Potentially unsafe calls (e.g., `cuMemAlloc`) from any thread are prohibited during the capture.
+    Global = 0,
+    /// Potentially unsafe calls from the capturing thread are prohibited.
+    ThreadLocal = 1,
+    /// No call is prohibited, it is the caller who avoids the unsafe ones.
+    Relaxed = 2,
+}
+/// Direction of a copy node, see `Graph::add_memcpy_raw`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CopyKind {
+    HostToDevice,
+    DeviceToHost,
+    DeviceToDevice,
+}
+/// A node of a `Graph`, whose handle stays valid while the graph (and executable graphs instantiated from it) is alive.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct GraphNode(CUgraphNode);
+impl GraphNode {
+    /// The raw node handle.
+    pub fn as_raw(&self) -> CUgraphNode {
+        self.0
+    }
+}
+/// A CUDA graph, a sequence of kernels and copies (with dependencies) that is instantiated once and launched many times
+/// with much less overhead than separate launches. Destroyed with `cuGraphDestroy` on drop.
+///
+/// Graphs are either recorded by `Stream::capture`, or built node by node. Captured launches should use device memory
+/// and values only (`Param::new_device`, `push_device`, `push_value`) with `Param::stream_ordered` temporaries,
+/// since host inputs and results need synchronization, which is not allowed during a capture.
+/// ```no_run
+/// # use cuda_min::{CaptureMode, DeviceBuffer, Device, Param, Stream};
+/// # let device = Device::init();
+/// # let module = device.compile("").unwrap();
+/// # let func = module.get_function("step").unwrap();
+/// let stream = Stream::new(&device).unwrap();
+/// let mut state = DeviceBuffer::<f32>::zeroed(&device, 1024).unwrap();
+/// let graph = stream
+///     .capture(CaptureMode::Global, |stream| {
+///         for _ in 0..16 {
+///             func.call_on(Param::new_device(&mut state).stream_ordered(), stream)?;
+///         }
+///         Ok(())
+///     })
+///     .unwrap();
+/// let exec = graph.instantiate().unwrap();
+/// for _ in 0..1000 {
+///     exec.launch(&stream).unwrap();
+/// }
+/// stream.sync().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct Graph<'a> {
+    graph: CUgraph,
+    device: &'a Device,
+    _marker: PhantomData<&'a ()>,
+}
+impl Drop for Graph<'_> {
+    fn drop(&mut self) {
+        let _ctx = self.device.bind();
+        let _ = unsafe { cuGraphDestroy(self.graph) };
+    }
+}
+impl<'a> Graph<'a> {
+    /// Create an empty graph, to be filled by `add_kernel` and the copy methods.
+    pub fn new(device: &'a Device) -> Result<Self, CUerror> {
+        let mut graph = CUgraph(ptr::null_mut());
+        let _ctx = device.bind()?;
+        unsafe { cuGraphCreate(&mut graph, 0)? }
+        Ok(Self {
+            graph,
+            device,
+            _marker: PhantomData,
+        })
+    }
+    /// The raw graph handle.
+    pub fn as_raw(&self) -> CUgraph {
+        self.graph
+    }
+    /// All the nodes of this graph.
+    pub fn nodes(&self) -> Result<Vec<GraphNode>, CUerror> {
+        let _ctx = self.device.bind()?;
+        let mut count = 0;
+        unsafe {
+            cuGraphGetNodes(self.graph, ptr::null_mut(), &mut count)?;
+            let mut nodes = vec![CUgraphNode(ptr::null_mut()); count];
+            cuGraphGetNodes(self.graph, nodes.as_mut_ptr(), &mut count)?;
+            nodes.truncate(count);
+            Ok(nodes.into_iter().map(GraphNode).collect())
+        }
+    }
+    /// The kernel nodes of this graph, e.g., to update the parameters of a captured launch with `GraphExec::set_kernel_params`.
+    pub fn kernel_nodes(&self) -> Result<Vec<GraphNode>, CUerror> {
+        let mut kernels = Vec::new();
+        for node in self.nodes()? {
+            let mut node_type = -1;
+            // CU_GRAPH_NODE_TYPE_KERNEL = 0
+            unsafe { cuGraphNodeGetType(node.0, &mut node_type)? }
+            if node_type == 0 {
+                kernels.push(node);
+            }
+        }
+        Ok(kernels)
+    }
+    /// Add a launch of `function` running after `dependencies`.
+    /// `param` must contain device memory and values only, which are copied into the node.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn add_kernel<R>(
+        &mut self,
+        function: Function<'a>,
+        param: &Param<'a, R>,
+        dependencies: &[GraphNode],
+    ) -> Result<GraphNode, CUerror> {
+        let mut storage = Vec::new();
+        let mut args = Vec::new();
+        let params = function.node_params(param, &mut storage, &mut args)?;
+        let mut node = CUgraphNode(ptr::null_mut());
+        let _ctx = self.device.bind()?;
+        unsafe {
+            cuGraphAddKernelNode(
+                &mut node,
+                self.graph,
+                dependencies.as_ptr() as _,
+                dependencies.len(),
+                &params,
+            )?
+        }
+        Ok(GraphNode(node))
+    }
+    /// Add a copy between device buffers with the same length, running after `dependencies`.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn add_copy<T>(
+        &mut self,
+        src: &'a DeviceBuffer<'_, T>,
+        dst: &'a DeviceBuffer<'_, T>,
+        dependencies: &[GraphNode],
+    ) -> Result<GraphNode, CUerror> {
+        if src.len() != dst.len() {
+            return Err(CUerror::INVALID_VALUE);
+        }
+        unsafe {
+            self.add_memcpy_raw(
+                dst.as_raw(),
+                src.as_raw(),
+                src.size(),
+                CopyKind::DeviceToDevice,
+                dependencies,
+            )
+        }
+    }
+    /// Add a 1d copy of `bytes` bytes, running after `dependencies`.
+    ///
+    /// # Safety
+    /// `dst` and `src` must be valid for `bytes` bytes (and `dst` must not be used elsewhere) whenever the graph
+    /// or an executable graph instantiated from it runs. Host memory should be pinned, e.g., a `PinnedBuffer`.
+    #[must_use = "You should check whether the execution successes."]
+    pub unsafe fn add_memcpy_raw(
+        &mut self,
+        dst: *mut c_void,
+        src: *const c_void,
+        bytes: usize,
+        kind: CopyKind,
+        dependencies: &[GraphNode],
+    ) -> Result<GraphNode, CUerror> {
+        // CU_MEMORYTYPE_HOST = 1, CU_MEMORYTYPE_DEVICE = 2
+        let (src_memory_type, dst_memory_type) = match kind {
+            CopyKind::HostToDevice => (1, 2),
+            CopyKind::DeviceToHost => (2, 1),
+            CopyKind::DeviceToDevice => (2, 2),
+        };
+        let copy = CUmemcpy3D {
+            src_x_in_bytes: 0,
+            src_y: 0,
+            src_z: 0,
+            src_lod: 0,
+            src_memory_type,
+            src_host: if src_memory_type == 1 {
+                src
+            } else {
+                ptr::null()
+            },
+            src_device: if src_memory_type == 2 {
+                src
+            } else {
+                ptr::null()
+            },
+            src_array: ptr::null_mut(),
+            reserved0: ptr::null_mut(),
+            src_pitch: 0,
+            src_height: 0,
+            dst_x_in_bytes: 0,
+            dst_y: 0,
+            dst_z: 0,
+            dst_lod: 0,
+            dst_memory_type,
+            dst_host: if dst_memory_type == 1 {
+                dst
+            } else {
+                ptr::null_mut()
+            },
+            dst_device: if dst_memory_type == 2 {
+                dst
+            } else {
+                ptr::null_mut()
+            },
+            dst_array: ptr::null_mut(),
+            reserved1: ptr::null_mut(),
+            dst_pitch: 0,
+            dst_height: 0,
+            width_in_bytes: bytes,
+            height: 1,
+            depth: 1,
+        };
+        let mut node = CUgraphNode(ptr::null_mut());
+        let _ctx = self.device.bind()?;
+        unsafe {
+            cuGraphAddMemcpyNode(
+                &mut node,
+                self.graph,
+                dependencies.as_ptr() as _,
+                dependencies.len(),
+                &copy,
+                self.device.context,
+            )?
+        }
+        Ok(GraphNode(node))
+    }
+    /// Instantiate an executable graph, which is independent of this graph afterwards.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn instantiate(&self) -> Result<GraphExec<'a>, CUerror> {
+        let mut exec = CUgraphExec(ptr::null_mut());
+        let _ctx = self.device.bind()?;
+        unsafe { cuGraphInstantiateWithFlags(&mut exec, self.graph, 0)? }
+        Ok(GraphExec {
+            exec,
+            device: self.device,
+            _marker: PhantomData,
+        })
+    }
+}
+/// An instantiated graph, launched as a whole with `launch`. Destroyed with `cuGraphExecDestroy` on drop.
+#[derive(Debug)]
+pub struct GraphExec<'a> {
+    exec: CUgraphExec,
+    device: &'a Device,
+    _marker: PhantomData<&'a ()>,
+}
+impl Drop for GraphExec<'_> {
+    fn drop(&mut self) {
+        let _ctx = self.device.bind();
+        let _ = unsafe { cuGraphExecDestroy(self.exec) };
+    }
+}
+impl<'a> GraphExec<'a> {
+    /// The raw executable graph handle.
+    pub fn as_raw(&self) -> CUgraphExec {
+        self.exec
+    }
+    /// Launch the whole graph in `stream`.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn launch(&self, stream: &Stream<'_>) -> CUresult {
+        let _ctx = self.device.bind()?;
+        unsafe { cuGraphLaunch(self.exec, stream.stream) }
+    }
+    /// Launch the whole graph in the null stream.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn launch_default(&self) -> CUresult {
+        let _ctx = self.device.bind()?;
+        unsafe { cuGraphLaunch(self.exec, Device::STREAM) }
+    }
+    /// Replace the function, geometry and arguments of a kernel node for the following launches,
+    /// without touching the graph it was instantiated from. `param` must contain device memory and values only.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn set_kernel_params<R>(
+        &mut self,
+        node: GraphNode,
+        function: Function<'a>,
+        param: &Param<'a, R>,
+    ) -> CUresult {
+        let mut storage = Vec::new();
+        let mut args = Vec::new();
+        let params = function.node_params(param, &mut storage, &mut args)?;
+        let _ctx = self.device.bind()?;
+        unsafe { cuGraphExecKernelNodeSetParams(self.exec, node.0, &params) }
+    }
 }
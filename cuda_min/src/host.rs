@@ -9,28 +9,241 @@ pub use cuda::*;
 // pub fn load(s: &str) -> Result<CUmodule, CUerror> {
 //     Device::init().load(s)
 // }
+/// A kernel argument collected by `Param`.
+#[derive(Debug)]
+pub enum ParamInput {
+    /// Host pointer and length (in bytes), copied into a temporary device buffer before launch.
+    Host(*const core::ffi::c_void, usize),
+    /// Device pointer, passed to the kernel as is.
+    Device(*mut core::ffi::c_void),
+    /// Scalar or `#[repr(C)]` struct, passed to the kernel by value.
+    Value(ParamValue),
+}
+/// An owned copy of a by-value kernel argument, stored with the size and alignment of its type.
+#[derive(Debug)]
+pub struct ParamValue {
+    ptr: core::ptr::NonNull<u8>,
+    layout: std::alloc::Layout,
+}
+impl ParamValue {
+    /// Copy `value` into a new allocation.
+    pub fn new<T: Copy>(value: T) -> Self {
+        let layout = std::alloc::Layout::new::<T>();
+        // zero sized allocation is not allowed, keep at least one byte.
+        let layout =
+            std::alloc::Layout::from_size_align(layout.size().max(1), layout.align()).unwrap();
+        unsafe {
+            let ptr = std::alloc::alloc(layout);
+            let Some(ptr) = core::ptr::NonNull::new(ptr) else {
+                std::alloc::handle_alloc_error(layout)
+            };
+            ptr.cast::<T>().write(value);
+            Self { ptr, layout }
+        }
+    }
+    /// Pointer to the stored value, which is what `cuLaunchKernel` expects in its argument array.
+    pub fn as_ptr(&self) -> *mut core::ffi::c_void {
+        self.ptr.as_ptr() as _
+    }
+}
+impl Clone for ParamValue {
+    fn clone(&self) -> Self {
+        unsafe {
+            let ptr = std::alloc::alloc(self.layout);
+            let Some(ptr) = core::ptr::NonNull::new(ptr) else {
+                std::alloc::handle_alloc_error(self.layout)
+            };
+            core::ptr::copy_nonoverlapping(self.ptr.as_ptr(), ptr.as_ptr(), self.layout.size());
+            Self {
+                ptr,
+                layout: self.layout,
+            }
+        }
+    }
+}
+impl Drop for ParamValue {
+    fn drop(&mut self) {
+        unsafe { std::alloc::dealloc(self.ptr.as_ptr(), self.layout) }
+    }
+}
+/// Where the kernel writes its result (its last parameter).
+#[derive(Debug)]
+pub enum ParamResult<'a, R> {
+    /// Host memory, uploaded before launch and downloaded after it.
+    Host(&'a mut [R]),
+    /// Device pointer and length (in elements), written in place and kept on GPU.
+    Device(
+        *mut core::ffi::c_void,
+        usize,
+        core::marker::PhantomData<&'a mut R>,
+    ),
+}
+impl<R> ParamResult<'_, R> {
+    /// Number of elements of the result.
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Host(result) => result.len(),
+            Self::Device(_, len, _) => *len,
+        }
+    }
+    /// Whether the result contains no element.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+/// How temporary device buffers of a launch (copies of host inputs and results) are allocated.
+#[derive(Debug, Clone, Copy)]
+pub enum TempAlloc {
+    /// The caching allocator of the device, blocks go back to it after the launch is synced.
+    Cached,
+    /// `cuMemAllocAsync` from the default pool, freed in stream order without host synchronization.
+    StreamOrdered,
+    /// `cuMemAllocFromPoolAsync` from the given pool, freed in stream order without host synchronization.
+    Pool(CUmemoryPool),
+}
+/// Launch geometry: grid and block sizes, dynamic shared memory, and the bounds of the problem.
+///
+/// Grids are rounded up, thus a launch may contain surplus threads, kernels should compare their
+/// `Dim3::global_thread_idx()` with the bounds (e.g., sent by `Param::push_bounds`) and return early.
+/// Invalid geometries (e.g., empty blocks) get an empty grid, which `validate` rejects.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LaunchConfig {
+    pub grid: crate::Dim3,
+    pub block: crate::Dim3,
+    pub shared_mem: u32,
+    pub bounds: crate::Dim3,
+}
+impl LaunchConfig {
+    /// Cover `bounds` with blocks of `block`, rounding the grid up.
+    pub fn new(bounds: impl Into<crate::Dim3>, block: impl Into<crate::Dim3>) -> Self {
+        let bounds = bounds.into();
+        let block = block.into();
+        let grid = if block.size() == 0 {
+            crate::Dim3::new(0, 0, 0)
+        } else {
+            bounds.div_ceil(block)
+        };
+        Self {
+            grid,
+            block,
+            shared_mem: 0,
+            bounds,
+        }
+    }
+    /// 1D launch for `n` elements, with blocks of (at most) 256 threads.
+    pub fn for_num_elems(n: u32) -> Self {
+        Self::new(n, n.clamp(1, 256))
+    }
+    /// 2D launch for a `width` x `height` problem, with `tile` x `tile` blocks.
+    pub fn for_2d(width: u32, height: u32, tile: u32) -> Self {
+        Self::new((width, height), (tile, tile))
+    }
+    /// 3D launch for a `width` x `height` x `depth` problem.
+    pub fn for_3d(width: u32, height: u32, depth: u32, block: impl Into<crate::Dim3>) -> Self {
+        Self::new((width, height, depth), block)
+    }
+    /// Change the block size, the grid is recalculated from the bounds.
+    pub fn block(self, block: impl Into<crate::Dim3>) -> Self {
+        Self {
+            shared_mem: self.shared_mem,
+            ..Self::new(self.bounds, block)
+        }
+    }
+    /// Set dynamic shared memory (in bytes) per block.
+    pub fn shared(mut self, size: u32) -> Self {
+        self.shared_mem = size;
+        self
+    }
+    /// Check the geometry against the limits of `device`, returns CUDA_ERROR_INVALID_VALUE if it could not be launched.
+    #[must_use = "You should check whether the execution successes."]
+    pub fn validate(&self, device: &Device) -> CUresult {
+        use DeviceAttribute::*;
+        let limit = |attrib| device.get_attribute(attrib).map(|x| x as u64);
+        let block = self.block;
+        let grid = self.grid;
+        let valid = block.size() > 0
+            && grid.size() > 0
+            && block.size() <= limit(MaxThreadsPerBlock)?
+            && block.x as u64 <= limit(MaxBlockDimX)?
+            && block.y as u64 <= limit(MaxBlockDimY)?
+            && block.z as u64 <= limit(MaxBlockDimZ)?
+            && grid.x as u64 <= limit(MaxGridDimX)?
+            && grid.y as u64 <= limit(MaxGridDimY)?
+            && grid.z as u64 <= limit(MaxGridDimZ)?
+            && self.shared_mem as u64 <= limit(MaxSharedMemoryPerBlock)?;
+        if valid {
+            Ok(())
+        } else {
+            Err(CUerror::INVALID_VALUE)
+        }
+    }
+}
 /// Function Param
 ///
 /// Packing and transfering parameters and returns to and from GPU.
 #[derive(Debug)]
 pub struct Param<'a, R> {
-    /// input pointers and lengths (in bytes)
-    pub input: Vec<(*const core::ffi::c_void, usize)>,
+    /// inputs, either host pointers and lengths (in bytes) or device pointers
+    pub input: Vec<ParamInput>,
     /// result, controls the numbers of tasks
-    pub result: &'a mut [R],
+    pub result: ParamResult<'a, R>,
     /// shared memory size, I have not yet tested it.
     pub shared_mem: u32,
     block_size: (u32, u32, u32),
     grid_size: (u32, u32, u32),
     len: usize,
+    bounds: crate::Dim3,
+    temp_alloc: TempAlloc,
+    auto_block: bool,
+    round_up: bool,
 }
 impl<'a, R> Param<'a, R> {
-    /// Set block 1d size. Currently only 1d size could be set directly (since result is a 1d vector)
+    /// Set block 1d size. Currently only 1d size could be set directly, use `launch_config` for 2d/3d launches.
     pub fn block_size(mut self, val: u32) -> Self {
         self.block_size = (val, 1, 1);
-        self.grid_size = ((self.len / self.block_size.0 as usize) as u32, 1, 1);
+        self.grid_size = (self.grid_len(val), 1, 1);
+        self.auto_block = false;
+        self
+    }
+    /// Round the 1d grid up rather than down, thus all the tasks are covered even if the block size does not divide them.
+    /// The surplus threads must be ignored by the kernel, e.g., by comparing with the bounds sent by `push_bounds`.
+    pub fn round_up(mut self) -> Self {
+        self.round_up = true;
+        self.grid_size.0 = self.grid_len(self.block_size.0);
+        self
+    }
+    /// Number of 1d blocks for `block` threads per block, rounded down unless `round_up` is set.
+    pub(crate) fn grid_len(&self, block: u32) -> u32 {
+        if block == 0 {
+            0
+        } else if self.round_up {
+            self.len.div_ceil(block as usize) as u32
+        } else {
+            (self.len / block as usize) as u32
+        }
+    }
+    /// Let the launch pick the 1d block size maximizing occupancy, given the shared memory set by `shared`.
+    /// The grid is rounded down like `block_size`, use `round_up` to cover all the tasks.
+    pub fn auto_block_size(mut self) -> Self {
+        self.auto_block = true;
         self
     }
+    /// Set grid, block, shared memory and bounds from `config`.
+    pub fn launch_config(mut self, config: LaunchConfig) -> Self {
+        self.block_size = (config.block.x, config.block.y, config.block.z);
+        self.grid_size = (config.grid.x, config.grid.y, config.grid.z);
+        self.shared_mem = config.shared_mem;
+        self.bounds = config.bounds;
+        self.len = config.bounds.size() as usize;
+        self.auto_block = false;
+        self
+    }
+    /// Push the bounds of the launch (a `Dim3`) by value, thus the kernel could ignore the surplus threads
+    /// of a rounded-up grid (see `round_up` and `launch_config`).
+    pub fn push_bounds(self) -> Self {
+        let bounds = self.bounds;
+        self.push_value(bounds)
+    }
     /// Set block size and grid size. You must ensure you handled it very well.
     /// Although all the functions are not very safe. This function is extremely unsafe.
     pub unsafe fn set_block_grid_size(
@@ -41,12 +254,40 @@ impl<'a, R> Param<'a, R> {
         self.block_size = block_size;
         self.grid_size = grid_size;
         self.len = block_size.0 as usize * grid_size.0 as usize;
+        self.auto_block = false;
+        // thread indices are u32, thus an overflowing product bounds nothing.
+        let bound = |block: u32, grid: u32| block.saturating_mul(grid);
+        self.bounds = crate::Dim3::new(
+            bound(block_size.0, grid_size.0),
+            bound(block_size.1, grid_size.1),
+            bound(block_size.2, grid_size.2),
+        );
         self
     }
     /// Generate parameter from its output, use output's length as number of tasks.
     pub fn new(result: &'a mut [R]) -> Self {
+        Self::with_result(ParamResult::Host(result))
+    }
+    /// Generate parameter writing into unified memory, which the host could read directly after syncing.
+    pub fn new_managed(result: &'a mut ManagedBuffer<'_, R>) -> Self {
+        Self::with_result(ParamResult::Device(
+            result.as_raw(),
+            result.len(),
+            core::marker::PhantomData,
+        ))
+    }
+    /// Generate parameter writing into a device buffer, which stays on GPU after the launch.
+    /// Use `DeviceBuffer::download` or `DeviceBuffer::to_vec` once the host really needs it.
+    pub fn new_device(result: &'a mut DeviceBuffer<'_, R>) -> Self {
+        Self::with_result(ParamResult::Device(
+            result.as_raw(),
+            result.len(),
+            core::marker::PhantomData,
+        ))
+    }
+    fn with_result(result: ParamResult<'a, R>) -> Self {
         let len = result.len();
-        let block_size = (32.min(len as u32), 1, 1);
+        let block_size = ((len as u32).clamp(1, 32), 1, 1);
         let grid_size = ((len / block_size.0 as usize) as u32, 1, 1);
         Self {
             input: Vec::new(),
@@ -55,29 +296,61 @@ impl<'a, R> Param<'a, R> {
             block_size,
             grid_size,
             len,
+            bounds: crate::Dim3::new(len as u32, 1, 1),
+            temp_alloc: TempAlloc::Cached,
+            auto_block: false,
+            round_up: false,
         }
     }
     /// Convenience push method, panic if the length is incorrect.
-    pub fn push<T>(self, item: &[T]) -> Self {
+    pub fn push<T>(self, item: &'a [T]) -> Self {
         self.checked_push(item).unwrap_or_else(|x| x)
     }
     /// Set real length
     pub fn len(mut self, len: usize) -> Self {
         self.len = len;
-        self.grid_size.0 = len as u32 / self.block_size.0;
+        self.grid_size.0 = self.grid_len(self.block_size.0);
+        self.bounds = crate::Dim3::new(len as u32, 1, 1);
         self
     }
     /// Push vectors into this parameter collection.
-    pub fn checked_push<T>(mut self, item: &[T]) -> Result<Self, Self> {
+    pub fn checked_push<T>(mut self, item: &'a [T]) -> Result<Self, Self> {
         // let size = core::mem::size_of_val(item);
         if let Some(size) = core::num::NonZero::new(core::mem::size_of::<T>() * item.len()) {
-            self.input.push((item.as_ptr() as _, size.get()));
+            self.input
+                .push(ParamInput::Host(item.as_ptr() as _, size.get()));
             Ok(self)
         } else {
-            self.input.push((core::ptr::null(), 0));
+            self.input.push(ParamInput::Host(core::ptr::null(), 0));
             Err(self)
         }
     }
+    /// Push a scalar or a `#[repr(C)]` struct, which is passed by value rather than as a pointer.
+    /// Its layout must match the kernel parameter, e.g., `n: u32` on the GPU side for `push_value(n as u32)`.
+    pub fn push_value<T: Copy>(mut self, value: T) -> Self {
+        self.input.push(ParamInput::Value(ParamValue::new(value)));
+        self
+    }
+    /// Push unified memory, its pointer is sent to the kernel directly without any copy.
+    pub fn push_managed<T>(mut self, buffer: &'a ManagedBuffer<'_, T>) -> Self {
+        self.input.push(ParamInput::Device(buffer.as_raw()));
+        self
+    }
+    /// Push a device buffer, its pointer is sent to the kernel directly without any copy.
+    pub fn push_device<T>(mut self, buffer: &'a DeviceBuffer<'_, T>) -> Self {
+        self.input.push(ParamInput::Device(buffer.as_raw()));
+        self
+    }
+    /// Allocate the temporaries of this launch in stream order from the default pool of the device.
+    pub fn stream_ordered(mut self) -> Self {
+        self.temp_alloc = TempAlloc::StreamOrdered;
+        self
+    }
+    /// Allocate the temporaries of this launch in stream order from `pool`.
+    pub fn pool(mut self, pool: &'a MemPool<'_>) -> Self {
+        self.temp_alloc = TempAlloc::Pool(pool.as_raw());
+        self
+    }
     /// Set shared mem
     pub fn shared(mut self, size: u32) -> Self {
         self.shared_mem = size;
@@ -57,6 +57,26 @@
 //! ```
 //! You might notice that, there is no unsafe here, but actually all functions are unsafe. Since you're calling cuda function, there is **actually no safety** here. No need to mark the whole function as unsafe.
 //!
+//! ## 2. Keeping data on GPU
+//!
+//! `Param::new` copies everything to GPU and copies the result back for every launch. With `DeviceBuffer`, data could stay on GPU across launches, and be downloaded only when needed:
+//! ```no_run
+//! use cuda_min::{Device, DeviceBuffer, Param};
+//! fn main() {
+//!     let ptx = std::fs::read_to_string("kernels.ptx").unwrap(); // contains `square(input, output)` and `add_one(input, output)`
+//!     let device = Device::init();
+//!     let module = device.compile(&ptx).unwrap();
+//!     let input = DeviceBuffer::from_slice(&device, &[1u32; 1024]).unwrap(); // uploaded once
+//!     let mut squared = DeviceBuffer::<u32>::zeroed(&device, 1024).unwrap();
+//!     let mut result = DeviceBuffer::<u32>::zeroed(&device, 1024).unwrap();
+//!     let square = module.get_function("square").unwrap();
+//!     let add_one = module.get_function("add_one").unwrap();
+//!     square.call(Param::new_device(&mut squared).push_device(&input)).unwrap().sync().unwrap();
+//!     add_one.call(Param::new_device(&mut result).push_device(&squared)).unwrap().sync().unwrap(); // the output of `square` is used directly
+//!     assert_eq!(result.to_vec().unwrap(), vec![2u32; 1024]); // the only download
+//! }
+//! ```
+//!
 //! ## Generates ptx code (for example, with nvptx64 backend)
 //!
 //! Although you might write ptx code directly, and you might mainly writting ptx asm in Rust code
@@ -118,6 +138,122 @@ macro_rules! repeat {
     ({ $($t:tt)* } $b:block) => { $(repeat!{@ $b $t})* };
 }
 
+/// 3D size or index of grids, blocks and threads.
+///
+/// It is `#[repr(C)]`, thus could be passed by value into kernels, e.g., the bounds of a `LaunchConfig`,
+/// which the kernel uses to ignore the surplus threads of the rounded-up grid.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Dim3 {
+    pub x: u32,
+    pub y: u32,
+    pub z: u32,
+}
+impl Dim3 {
+    pub const fn new(x: u32, y: u32, z: u32) -> Self {
+        Self { x, y, z }
+    }
+    /// Number of elements (or threads) it covers.
+    pub const fn size(&self) -> u64 {
+        self.x as u64 * self.y as u64 * self.z as u64
+    }
+    /// Whether `index` lies in the bounds of `self`.
+    pub const fn contains(&self, index: Dim3) -> bool {
+        index.x < self.x && index.y < self.y && index.z < self.z
+    }
+    /// Elementwise `self / rhs`, rounded up, which is the grid covering `self` with blocks of `rhs`.
+    pub const fn div_ceil(&self, rhs: Dim3) -> Self {
+        Self::new(
+            self.x.div_ceil(rhs.x),
+            self.y.div_ceil(rhs.y),
+            self.z.div_ceil(rhs.z),
+        )
+    }
+    /// Global index of the current thread, i.e., `block_idx * block_dim + thread_idx` in each dimension.
+    #[cfg(target_arch = "nvptx64")]
+    #[inline(always)]
+    pub fn global_thread_idx() -> Self {
+        use core::arch::nvptx::*;
+        unsafe {
+            Self::new(
+                _block_idx_x() as u32 * _block_dim_x() as u32 + _thread_idx_x() as u32,
+                _block_idx_y() as u32 * _block_dim_y() as u32 + _thread_idx_y() as u32,
+                _block_idx_z() as u32 * _block_dim_z() as u32 + _thread_idx_z() as u32,
+            )
+        }
+    }
+}
+impl From<u32> for Dim3 {
+    fn from(x: u32) -> Self {
+        Self::new(x, 1, 1)
+    }
+}
+impl From<(u32, u32)> for Dim3 {
+    fn from((x, y): (u32, u32)) -> Self {
+        Self::new(x, y, 1)
+    }
+}
+impl From<(u32, u32, u32)> for Dim3 {
+    fn from((x, y, z): (u32, u32, u32)) -> Self {
+        Self::new(x, y, z)
+    }
+}
+
+/// The part of a multi-GPU launch handled by one device, passed by value as the last input of the kernel.
+///
+/// The kernel writes `len` elements of its result, which is the range `offset..offset + len` of the whole result,
+/// while the inputs are not split, thus they should be indexed with `offset + idx`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Chunk {
+    pub offset: u64,
+    pub len: u64,
+}
+
+/// Declare a module-level variable in `.global` space, which could be accessed by `Module::global` on the host.
+/// ```ignore
+/// cuda_min::device_global!(pub static mut COUNTER: u32 = 0;);
+/// ```
+#[macro_export]
+macro_rules! device_global {
+    ($vis:vis static mut $name:ident: $ty:ty = $init:expr;) => {
+        #[unsafe(no_mangle)]
+        $vis static mut $name: $ty = $init;
+    };
+}
+
+/// Declare a module-level variable in `.const` space (uninitialized, up to 64KiB in total), which should be written by `Module::global` on the host before launching kernels.
+/// It yields a function with the same name, returning a reference to the variable.
+/// Requires `#![feature(asm_experimental_arch)]` in the GPU crate.
+/// ```ignore
+/// cuda_min::device_const!(pub static VALS: [u32; 1024];);
+/// let first = VALS()[0];
+/// ```
+#[macro_export]
+macro_rules! device_const {
+    ($vis:vis static $name:ident: $ty:ty;) => {
+        core::arch::global_asm!(
+            concat!(".visible .const .align {align} .b8 ", stringify!($name), "[{size}];"),
+            align = const core::mem::align_of::<$ty>(),
+            size = const core::mem::size_of::<$ty>(),
+        );
+        #[allow(non_snake_case)]
+        #[inline(always)]
+        $vis fn $name() -> &'static $ty {
+            let ptr: u64;
+            unsafe {
+                // converts the `.const` address into a generic address, which normal loads could use.
+                core::arch::asm!(
+                    concat!("cvta.const.u64 {ptr}, ", stringify!($name), ";"),
+                    ptr = out(reg64) ptr,
+                    options(pure, nomem, nostack),
+                );
+                &*(ptr as *const $ty)
+            }
+        }
+    };
+}
+
 #[cfg(target_arch = "nvptx64")]
 #[cfg_attr(all(target_arch = "nvptx64", feature = "panic-handler"), panic_handler)]
 unsafe fn ph(_info: &core::panic::PanicInfo) -> ! {
@@ -6,6 +6,7 @@ fn main() {
     println!("PTX Code:");
     println!("{A}");
     let device = Device::init();
+    println!("{}", device.properties().unwrap());
 
     let module = device.compile(A).unwrap();
     let func = module.get_function("show_details").unwrap();
@@ -12,12 +12,15 @@ use cuda_min;
 pub unsafe extern "ptx-kernel" fn vec_add(
     input1: *const i32,
     input2: *const i32,
-    offset: *const i32,
+    offset: i32,
+    bounds: cuda_min::Dim3,
     output: *mut i32,
 ) {
     unsafe {
-        let offset = *offset;
         let index = _block_idx_x() * _block_dim_x() + _thread_idx_x();
+        if index as u32 >= bounds.x {
+            return; // surplus thread of the rounded-up grid.
+        }
         let left = *input1.wrapping_add(index as usize);
         let right = *input2.wrapping_add(index as usize);
         *output.wrapping_add(index as usize) = left + right + offset;
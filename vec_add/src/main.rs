@@ -1,6 +1,6 @@
 use std::time::Instant;
 
-use cuda_min::{Device, Param};
+use cuda_min::{Device, Event, Param};
 fn main() {
     const A: &'static str = include_str!(concat!(env!("OUT_DIR"), "/gpu_ptx_code.ptx"));
     println!("PTX Code:");
@@ -9,8 +9,9 @@ fn main() {
 
     let module = device.compile(A).unwrap();
     let func = module.get_function("vec_add").unwrap();
+    let (min_grid, block) = func.max_potential_block_size(0, 0).unwrap();
     println!(
-        "max thread per block is {}",
+        "max thread per block is {}, occupancy prefers {block} threads per block and at least {min_grid} blocks",
         func.get_max_thread_per_block().unwrap()
     );
     const LEN: usize = 1048576;
@@ -18,18 +19,28 @@ fn main() {
     let mut ret = vec![0i32; LEN]; // ret could also be input, and its length decided the max avaliable tasks.
     let input1: Vec<_> = (0..LEN as i32).collect(); // normal parameter, its length is not restricted.
     let input2: Vec<_> = (0..LEN as i32).map(|x| x + 2).collect(); // normal parameter, its length is not restricted.
-    let offset: Vec<_> = vec![1i32]; // You should restrict that, you never visit a range larger than the offset itself has
+    let offset = 1i32; // scalars are passed by value, no device buffer is needed.
     let mut param = Param::new(&mut ret)
-        .block_size(1024)
+        .auto_block_size()
+        .round_up() // the block size picked may not divide LEN, the kernel checks the bounds.
         .push(&input1)
         .push(&input2)
-        .push(&offset);
+        .push_value(offset)
+        .push_bounds();
 
+    let start = Event::new(&device).unwrap();
+    let end = Event::new(&device).unwrap();
+    start.record_default().unwrap();
     let res = func.call(param).unwrap();
+    end.record_default().unwrap();
     res.sync().unwrap();
     println!("{:?}", now.elapsed());
+    println!(
+        "GPU cost (copies + kernel) {:?}",
+        end.elapsed_since(&start).unwrap()
+    );
     ret.into_iter().enumerate().for_each(|(n, i)| {
-        if i != input1[n] + input2[n] + offset[0] {
+        if i != input1[n] + input2[n] + offset {
             println!("Add Error at {n}: {i}")
         }
     });